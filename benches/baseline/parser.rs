@@ -0,0 +1,228 @@
+// The parser as it was before the boundary scanner was rewritten, the
+// baseline for benches/scan.rs.  Only the debug println!s are taken out, they
+// are not part of the parsing.  Loops do not nest and comments are not
+// always skipped, which is why the old main.rs stripped comments before
+// calling parse_module.
+#[allow(unused_imports)]
+use nom::branch::alt;
+use nom::bytes::complete::tag;
+use nom::bytes::complete::take_until;
+use nom::bytes::complete::take_while;
+use nom::character::complete::multispace1;
+use nom::character::complete::space0;
+use nom::combinator::opt;
+use nom::multi::{many0, many1};
+use nom::sequence::delimited;
+use nom::sequence::terminated;
+use nom::sequence::tuple;
+use nom::IResult;
+
+#[derive(Debug)]
+pub struct Module {
+    pub preamble: String,
+    pub items: Vec<ModuleItem>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Question {
+    pub header: String,
+    pub markdown: String,
+}
+impl Question {
+    fn new(header: &str, markdown: &str) -> Self {
+        Question {
+            header: String::from(header.trim()),
+            markdown: String::from(markdown.trim()),
+        }
+    }
+
+    pub fn render_markdown(&self) -> &str {
+        return &self.markdown;
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Grid {
+    tag: Tag,
+    markdown: String,
+}
+
+impl Grid {
+    fn new(tag: Tag, markdown: &str) -> Self {
+        Grid {
+            tag,
+            markdown: String::from(markdown.trim()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Loop {
+    tag: Tag,
+    markdown: String,
+    questions: Vec<ModuleItem>,
+}
+impl Loop {
+    fn new(tag: Tag, markdown: &str, questions: Vec<ModuleItem>) -> Self {
+        Loop {
+            tag,
+            markdown: String::from(markdown.trim()),
+            questions,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ModuleItem {
+    Question(Question),
+    Loop(Loop),
+    Grid(Grid),
+}
+
+impl ModuleItem {
+    fn new_question(header: &str, markdown: &str) -> Self {
+        ModuleItem::Question(Question::new(header, markdown))
+    }
+    fn new_loop(tag: Tag, markdown: &str, items: Vec<ModuleItem>) -> Self {
+        ModuleItem::Loop(Loop::new(tag, markdown, items))
+    }
+    fn new_grid(tag: Tag, markdown: &str) -> Self {
+        ModuleItem::Grid(Grid::new(tag, markdown))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Tag {
+    name: String,
+    params: String,
+}
+
+impl Tag {
+    fn new(name: &str, params: &str) -> Self {
+        Tag {
+            name: String::from(name.trim()),
+            params: String::from(params.trim()),
+        }
+    }
+}
+
+fn take_until_next_module_item(input: &str) -> IResult<&str, &str> {
+    let mut chars = input.char_indices();
+    let mut char2 = input.chars();
+    let _ = char2.next();
+
+    while let Some((current_index, current_char)) = chars.next() {
+        let optional_next_char = char2.next();
+        match (current_char, optional_next_char) {
+            // there is a comment scan until \n or EOS...
+            ('/', Some('/')) => {
+                while let Some((_, in_comment_char)) = chars.next() {
+                    let _ = char2.next();
+                    if in_comment_char == '\n' {
+                        break;
+                    }
+                }
+            }
+            // hit new question?
+            ('[', Some(next_char)) if next_char.is_uppercase() => {
+                return Ok((&input[current_index..], &input[0..current_index]));
+            }
+            // hit grid/loop?
+            ('<', Some(_)) => {
+                // safely calculate start and end to make sure that
+                // we dont hit any multi-byte characters.  If we hit a multibyte
+                // character, then it is not the end of the module item.
+                let start = (current_index + 1).min(input.len());
+                let end = (current_index + 5).min(input.len());
+                if input.is_char_boundary(end) && end > start {
+                    let tag = &input[start..end];
+                    if tag == "loop" || tag == "grid" {
+                        //println!("--- found tag {}", tag);
+                        return Ok((&input[current_index..], &input[0..current_index]));
+                    }
+                }
+            }
+            // keep going
+            (_, _) => {}
+        }
+    }
+    Ok(("", input))
+}
+
+fn parse_question(input: &str) -> IResult<&str, ModuleItem> {
+    let (input, header) = delimited(tag("["), take_until("]"), tag("]"))(input)?;
+    let (input, markdown) = take_until_next_module_item(input)?;
+
+    let item = ModuleItem::new_question(header, markdown);
+    Ok((input, item))
+}
+
+fn parse_tag(input: &str) -> IResult<&str, Tag> {
+    let (input, (_x1, _x2, name, params, _x3)) = tuple((
+        tag("<"),
+        space0,
+        alt((tag("grid"), tag("loop"))),
+        take_until(">"),
+        tag(">"),
+    ))(input)?;
+
+    let tag = Tag::new(name, params);
+    Ok((input, tag))
+}
+
+fn parse_loop(input: &str) -> IResult<&str, (&str, Vec<ModuleItem>)> {
+    let (input, markdown) = terminated(take_until("</loop>"), tag("</loop>"))(input)?;
+    let loop_input = markdown;
+    let (_, items) = many0(parse_question_loop_grid)(loop_input)?;
+
+    Ok((input, (markdown, items)))
+}
+
+fn parse_grid(input: &str) -> IResult<&str, &str> {
+    let (input, markdown) = terminated(take_until("</grid>"), tag("</grid>"))(input)?;
+    Ok((input, markdown))
+}
+
+fn comment(input: &str) -> IResult<&str, &str> {
+    let (input, (_, comment, _)) =
+        tuple((tag("//"), take_while(|c| c != '\n'), opt(tag("\n"))))(input)?;
+    Ok((input, comment))
+}
+
+fn parse_whitespace_or_comment(input: &str) -> IResult<&str, &str> {
+    //let (input, _) = many0(alt((multispace0, comment)))(input)?;
+    let (input, _) = many0(alt((comment, multispace1)))(input)?;
+    Ok((input, ""))
+}
+
+fn parse_question_loop_grid(input: &str) -> IResult<&str, ModuleItem> {
+    // nom nom nom any whitespace..
+    let (input, _) = parse_whitespace_or_comment(input)?;
+    alt((parse_question, parse_loop_grid))(input)
+}
+
+fn parse_loop_grid(input: &str) -> IResult<&str, ModuleItem> {
+    let (input, tag) = parse_tag(input)?;
+    match &tag.name[..] {
+        "grid" => {
+            let (input, markdown) = parse_grid(input)?;
+            Ok((input, ModuleItem::new_grid(tag, markdown)))
+        }
+        "loop" => {
+            let (input, (markdown, questions)) = parse_loop(input)?;
+            Ok((input, ModuleItem::new_loop(tag, markdown, questions)))
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub fn parse_module(input: &str) -> IResult<&str, Module> {
+    let (input, preamble) = take_until_next_module_item(input)?;
+
+    let (input, items) = many0(parse_question_loop_grid)(input)?;
+    let m = Module {
+        preamble: String::from(preamble),
+        items,
+    };
+    return Ok((input, m));
+}
@@ -0,0 +1,188 @@
+// Times the boundary scanner against the char by char scanner it replaced,
+// and whole module parses against the parse_module the old main.rs timed,
+// on a synthetic module the size of the production Connect modules or on
+// the modules given as arguments.  A name that is not a local file is
+// fetched from the production Connect modules:
+//
+//   cargo bench --bench scan
+//   cargo bench --bench scan -- module1.txt module2.txt
+use nom1::{ModuleLoader, ModuleSource, DEFAULT_BASE_URL};
+use regex::Regex;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+#[path = "../src/scan.rs"]
+#[allow(dead_code, unused_imports)]
+mod scan;
+
+#[path = "baseline/parser.rs"]
+#[allow(dead_code, unused_imports, clippy::all)]
+mod baseline;
+
+// the scanner before the byte level rewrite, kept as the baseline.
+fn char_scanner(input: &str) -> Option<usize> {
+    let mut chars = input.char_indices();
+    let mut char2 = input.chars();
+    let _ = char2.next();
+
+    while let Some((current_index, current_char)) = chars.next() {
+        let optional_next_char = char2.next();
+        match (current_char, optional_next_char) {
+            ('/', Some('/')) => {
+                for (_, in_comment_char) in chars.by_ref() {
+                    let _ = char2.next();
+                    if in_comment_char == '\n' {
+                        break;
+                    }
+                }
+            }
+            ('[', Some(next_char)) if next_char.is_uppercase() => return Some(current_index),
+            ('<', Some(_)) => {
+                let tag = &input[current_index + 1..];
+                if ["loop", "grid", "/loop", "/grid"]
+                    .iter()
+                    .any(|name| tag.starts_with(name))
+                {
+                    return Some(current_index);
+                }
+            }
+            (_, _) => {}
+        }
+    }
+    None
+}
+
+// steps from item to item the way the parser does, returning the count
+fn walk(input: &str, scanner: fn(&str) -> Option<usize>) -> usize {
+    let mut rest = input;
+    let mut items = 0;
+    while let Some(start) = scanner(rest) {
+        items += 1;
+        rest = &rest[start + 1..];
+    }
+    items
+}
+
+// about the size of module1.txt
+fn synthetic_module() -> String {
+    let mut module = String::from("// Module 1: About you\n\n");
+    for indx in 1..=400 {
+        module.push_str(&format!(
+            "[D_{indx}? displayif=equals(D_{prev},1)] ¿Cuál es su situación laboral actual? \
+             Por favor, seleccione todas las opciones que correspondan a usted o a su \
+             hogar en los últimos 12 meses.\n\
+             // asked of everyone who answered D_{prev}\n\
+             (1) Trabajo a tiempo completo -> D_{next}\n\
+             (2) Trabajo a tiempo parcial\n\
+             (3) Otro, por favor especifique: |__|id=D_{indx}_OTH xor=D_{indx}|\n\
+             (77) Prefiero no contestar\n\
+             #NR -> D_{next}\n\n",
+            prev = indx - 1,
+            next = indx + 1,
+        ));
+        if indx % 50 == 0 {
+            module.push_str(&format!(
+                "<loop max=10 id=LOOP{indx}>\n\
+                 [L{indx}] ¿Cuál es el nombre de la persona #loop en su hogar?\n\
+                 |__|id=L{indx}_NAME|\n\
+                 </loop>\n\n\
+                 <grid id=G{indx}>\n\
+                 ¿Con qué frecuencia...?\n\
+                 (1) Nunca\n(2) A veces\n(3) Siempre\n\
+                 [G{indx}_1] come frutas\n[G{indx}_2] come verduras\n\
+                 </grid>\n\n"
+            ));
+        }
+    }
+    module
+}
+
+// the average time of f, run for about a second
+fn time(mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    let mut runs = 0;
+    while runs < 10 || start.elapsed() < Duration::from_secs(1) {
+        f();
+        runs += 1;
+    }
+    start.elapsed() / runs
+}
+
+fn report(what: &str, bytes: usize, elapsed: Duration) {
+    let rate = bytes as f64 / elapsed.as_secs_f64() / 1_000_000.0;
+    println!("  {:<24} {:>12.1?} {:>10.1} MB/s", what, elapsed, rate);
+}
+
+fn bench(name: &str, module: &str) {
+    println!("{} ({} bytes)", name, module.len());
+    assert_eq!(walk(module, char_scanner), walk(module, scan::next_item));
+
+    let old = time(|| {
+        black_box(walk(black_box(module), char_scanner));
+    });
+    let new = time(|| {
+        black_box(walk(black_box(module), scan::next_item));
+    });
+    report("char scanner", module.len(), old);
+    report("byte scanner", module.len(), new);
+    println!(
+        "  {:<24} {:>12.1}x",
+        "speedup, scanner",
+        old.as_secs_f64() / new.as_secs_f64()
+    );
+
+    // the old main.rs stripped the comments before parsing, the baseline
+    // does not skip all of them
+    let stripped = Regex::new(r"//.*").unwrap().replace_all(module, "");
+    if let Ok((rest, parsed)) = baseline::parse_module(&stripped) {
+        if !rest.trim().is_empty() {
+            println!("  the baseline stops after {} items", parsed.items.len());
+        }
+    }
+    let baseline = time(|| {
+        let _ = black_box(baseline::parse_module(black_box(&stripped)));
+    });
+    let borrowed = time(|| {
+        let _ = black_box(nom1::borrowed::Module::parse(black_box(module)));
+    });
+    let owned = time(|| {
+        let _ = black_box(nom1::Module::parse(black_box(module)));
+    });
+    report("baseline parse_module", stripped.len(), baseline);
+    report("borrowed::Module::parse", module.len(), borrowed);
+    report("Module::parse", module.len(), owned);
+    // the baseline only slices the module like borrowed does, Module::parse
+    // also works out responses, inputs, attributes and spans
+    println!(
+        "  {:<24} {:>12.1}x",
+        "speedup, borrowed",
+        baseline.as_secs_f64() / borrowed.as_secs_f64()
+    );
+    println!(
+        "  {:<24} {:>12.1}x",
+        "speedup, Module::parse",
+        baseline.as_secs_f64() / owned.as_secs_f64()
+    );
+}
+
+fn main() {
+    // cargo bench passes --bench
+    let files: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    if files.is_empty() {
+        bench("synthetic module", &synthetic_module());
+    }
+    let mut loader = ModuleLoader::new();
+    for file in files {
+        match loader.load(&ModuleSource::from_arg(&file, Some(DEFAULT_BASE_URL))) {
+            Ok(modules) => {
+                for module in modules {
+                    bench(&module.name, &module.text);
+                }
+            }
+            Err(e) => eprintln!("{}: {}", file, e),
+        }
+    }
+}
@@ -0,0 +1,251 @@
+// A module that borrows its text from the source instead of copying it.
+// Parsing one only records where each header, body and tag is, nothing is
+// allocated apart from the item lists, which makes it the cheap way to check
+// or scan many modules.  into_owned gives the full crate::Module, with the
+// responses, inputs and attributes worked out, when it is needed.
+//
+// This is also where the grammar of questions, loops and grids is, the
+// owned parser builds its items from what parse_item finds.
+use crate::{
+    consumed, diagnose, parse_tag_parts, parse_whitespace_or_comment, take_block_body,
+    take_until_next_module_item, ParseError,
+};
+use nom::bytes::complete::{tag, take_until};
+use nom::multi::many0;
+use nom::sequence::delimited;
+use nom::IResult;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Module<'a> {
+    source: &'a str,
+    pub preamble: &'a str,
+    pub items: Vec<ModuleItem<'a>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ModuleItem<'a> {
+    Question(Question<'a>),
+    Loop(Loop<'a>),
+    Grid(Grid<'a>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Question<'a> {
+    // from the [ to the end of the markdown
+    pub source: &'a str,
+    // between the [ and ]
+    pub header: &'a str,
+    pub markdown: &'a str,
+}
+
+// <loop max=5> has the name loop and the params max=5
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Tag<'a> {
+    // from the < to the >
+    pub source: &'a str,
+    pub name: &'a str,
+    pub params: &'a str,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Loop<'a> {
+    // from <loop to the end of </loop>
+    pub source: &'a str,
+    pub tag: Tag<'a>,
+    pub markdown: &'a str,
+    pub items: Vec<ModuleItem<'a>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Grid<'a> {
+    // from <grid to the end of </grid>
+    pub source: &'a str,
+    pub tag: Tag<'a>,
+    pub markdown: &'a str,
+}
+
+impl<'a> Module<'a> {
+    // Parses a whole module, like crate::Module::parse.
+    pub fn parse(source: &'a str) -> Result<Module<'a>, ParseError> {
+        let (rest, preamble) = take_until_next_module_item(source).unwrap_or(("", source));
+        let (rest, items) = many0(parse_item)(rest).unwrap_or((rest, vec![]));
+        let (rest, _) = parse_whitespace_or_comment(rest).unwrap_or((rest, ""));
+        if !rest.is_empty() {
+            return Err(diagnose(source, rest));
+        }
+        Ok(Module {
+            source,
+            preamble,
+            items,
+        })
+    }
+
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    pub fn into_owned(self) -> crate::Module {
+        let mut items: Vec<_> = self.items.iter().map(ModuleItem::to_owned_item).collect();
+        crate::resolve_spans(&mut items, self.source);
+        crate::Module {
+            preamble: String::from(self.preamble),
+            items,
+        }
+    }
+}
+
+impl<'a> ModuleItem<'a> {
+    // the text the item was parsed from
+    pub fn source(&self) -> &'a str {
+        match self {
+            ModuleItem::Question(q) => q.source,
+            ModuleItem::Loop(l) => l.source,
+            ModuleItem::Grid(g) => g.source,
+        }
+    }
+
+    // the owned item, its spans are still addresses until they are resolved
+    // against the source.
+    pub(crate) fn to_owned_item(&self) -> crate::ModuleItem {
+        match self {
+            ModuleItem::Question(q) => {
+                crate::ModuleItem::located_question(q.source, q.header, q.markdown)
+            }
+            ModuleItem::Loop(l) => {
+                let items = l.items.iter().map(ModuleItem::to_owned_item).collect();
+                let mut item = crate::ModuleItem::new_loop(l.tag.to_owned_tag(), l.markdown, items);
+                item.locate_block(l.source, l.markdown, l.tag.closing(l.source));
+                item
+            }
+            ModuleItem::Grid(g) => {
+                let mut item = crate::ModuleItem::new_grid(g.tag.to_owned_tag(), g.markdown);
+                item.locate_block(g.source, g.markdown, g.tag.closing(g.source));
+                item
+            }
+        }
+    }
+}
+
+impl<'a> Question<'a> {
+    // the header up to the first space, ? or !
+    pub fn id(&self) -> &'a str {
+        let header = self.header.trim_start();
+        let end = header
+            .find(|c: char| c.is_whitespace() || ",?!=".contains(c))
+            .unwrap_or(header.len());
+        &header[..end]
+    }
+}
+
+impl<'a> Tag<'a> {
+    fn to_owned_tag(self) -> crate::Tag {
+        crate::Tag::located(self.source, self.name, self.params)
+    }
+
+    // the </name> at the end of the source of its block
+    fn closing(&self, source: &'a str) -> &'a str {
+        &source[source.len() - self.name.len() - "</>".len()..]
+    }
+}
+
+fn parse_question(input: &str) -> IResult<&str, Question<'_>> {
+    let start = input;
+    let (input, header) = delimited(tag("["), take_until("]"), tag("]"))(input)?;
+    let (input, markdown) = take_until_next_module_item(input)?;
+    let question = Question {
+        source: consumed(start, input).trim_end(),
+        header,
+        markdown: markdown.trim(),
+    };
+    Ok((input, question))
+}
+
+// A loop body is parsed as it is read, a nested <loop> consumes its own
+// </loop> so the first one left over closes this loop.
+pub(crate) fn parse_loop_grid(input: &str) -> IResult<&str, ModuleItem<'_>> {
+    let start = input;
+    let (input, (name, params)) = parse_tag_parts(input)?;
+    let open = Tag {
+        source: consumed(start, input),
+        name,
+        params: params.trim(),
+    };
+    let body_start = input;
+    if name == "grid" {
+        let (input, markdown) = take_block_body(input, name)?;
+        let grid = Grid {
+            source: consumed(start, input),
+            tag: open,
+            markdown: markdown.trim(),
+        };
+        return Ok((input, ModuleItem::Grid(grid)));
+    }
+    let (input, items) = many0(parse_item)(input)?;
+    let (input, _) = parse_whitespace_or_comment(input)?;
+    let markdown = consumed(body_start, input).trim();
+    let (input, _) = tag("</loop>")(input)?;
+    let l = Loop {
+        source: consumed(start, input),
+        tag: open,
+        markdown,
+        items,
+    };
+    Ok((input, ModuleItem::Loop(l)))
+}
+
+pub(crate) fn parse_item(input: &str) -> IResult<&str, ModuleItem<'_>> {
+    let (input, _) = parse_whitespace_or_comment(input)?;
+    match parse_question(input) {
+        Ok((input, question)) => Ok((input, ModuleItem::Question(question))),
+        Err(_) => parse_loop_grid(input),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_borrowed_module() {
+        let markdown = "intro\n[Q1? min=0] one\n(1) yes -> Q2\n<loop max=2>\n  [L1] a\n  <grid id=G>\n(1) x\n[G1] r\n</grid>\n</loop>\n[Q2] two";
+        let module = Module::parse(markdown).unwrap();
+        assert_eq!(module.preamble, "intro\n");
+        let ModuleItem::Question(q1) = &module.items[0] else {
+            panic!("expected a question");
+        };
+        assert_eq!(q1.id(), "Q1");
+        assert_eq!(q1.markdown, "one\n(1) yes -> Q2");
+        let ModuleItem::Loop(l) = &module.items[1] else {
+            panic!("expected a loop");
+        };
+        assert_eq!(l.tag.params, "max=2");
+        assert_eq!(l.items[1].source(), "<grid id=G>\n(1) x\n[G1] r\n</grid>");
+        assert!(l.source.ends_with("</grid>\n</loop>"));
+
+        // the same module, spans included, as parsing it owned
+        let owned = module.into_owned();
+        let expected = crate::Module::parse(markdown).unwrap();
+        assert_eq!(owned, expected);
+
+        let e = Module::parse("[Q1] hi\n<loop>\n[L1] a\n  </grid>\n</loop>").unwrap_err();
+        assert_eq!(e.message, "unmatched </grid>");
+        assert_eq!(e.line, 4);
+    }
+
+    #[test]
+    fn test_same_as_owned() {
+        let inputs = [
+            "intro\n[Q1] one\n<loop max=2>\n[L1] a\n</loop>\n[Q2] two",
+            "// c\n<loop max=2>\n//</loop>\n(1) yes -> Q2\n<loop max=2 counter=Q1>",
+            "[Q1] one\n</grid>\n[Q2] two",
+            "<grid id=G>\n(1) a\n[G_1] r\n",
+            "[Q1 hi\n",
+        ];
+        for input in inputs {
+            let borrowed = Module::parse(input).map(Module::into_owned);
+            assert_eq!(borrowed, crate::Module::parse(input), "{:?}", input);
+        }
+        let e = crate::Module::parse(inputs[1]).unwrap_err();
+        assert_eq!(e.message, "unterminated <loop> opened at line 2");
+    }
+}
@@ -0,0 +1,335 @@
+use crate::span::LineIndex;
+use crate::{Module, ModuleItem, ParseError, Question, Span};
+use std::fmt;
+
+// A lossless view of a module.  Every byte of the source, comments, blank
+// lines and indentation included, belongs to exactly one leaf so writing the
+// tree back gives the source unchanged apart from any edits:
+//
+//   [Q1] one        Question { Header "[Q1]", Whitespace " ", Text "one" }
+//     // a note     Whitespace "\n  ", Comment "// a note", Whitespace "\n"
+//   <loop max=2>    Loop { OpenTag "<loop max=2>", Whitespace "\n", Question
+//   [L1] two               { .. }, Whitespace "\n", CloseTag "</loop>" }
+//   </loop>
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SyntaxKind {
+    Whitespace,
+    // "//" up to the end of the line
+    Comment,
+    // markdown, the prompt and columns of a grid, or anything the parser
+    // could not make sense of
+    Text,
+    // the [ID attributes] of a question
+    Header,
+    OpenTag,
+    CloseTag,
+    Question,
+    Loop,
+    Grid,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SyntaxNode {
+    pub kind: SyntaxKind,
+    // the id of a question, loop or grid as it was parsed
+    pub id: Option<String>,
+    // where the node was in the parsed text, edits do not move it
+    pub span: Span,
+    // the text of a leaf, empty when the node has children
+    text: String,
+    pub children: Vec<SyntaxNode>,
+}
+
+impl SyntaxNode {
+    pub fn new(kind: SyntaxKind, text: &str) -> Self {
+        SyntaxNode {
+            kind,
+            id: None,
+            span: Span::default(),
+            text: String::from(text),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    // Replaces the text of the node, and of all its children, with text.  An
+    // empty string removes the node from the output.
+    pub fn set_text(&mut self, text: &str) {
+        self.text = String::from(text);
+        self.children.clear();
+    }
+
+    pub fn find(&self, id: &str) -> Option<&SyntaxNode> {
+        if self.id.as_deref() == Some(id) {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find(id))
+    }
+
+    pub fn find_mut(&mut self, id: &str) -> Option<&mut SyntaxNode> {
+        if self.id.as_deref() == Some(id) {
+            return Some(self);
+        }
+        self.children
+            .iter_mut()
+            .find_map(|child| child.find_mut(id))
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_leaf() {
+            return write!(f, "{}", self.text);
+        }
+        for child in &self.children {
+            write!(f, "{}", child)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SyntaxTree {
+    pub nodes: Vec<SyntaxNode>,
+}
+
+impl SyntaxTree {
+    pub fn parse(source: &str) -> Result<SyntaxTree, ParseError> {
+        let module = Module::parse(source)?;
+        Ok(SyntaxTree::build(source, &module))
+    }
+
+    // Whatever the parser skipped over is kept as Text, so even a broken
+    // module writes back unchanged.
+    pub fn parse_recovering(source: &str) -> (SyntaxTree, Vec<ParseError>) {
+        let (module, diagnostics) = Module::parse_recovering(source);
+        (SyntaxTree::build(source, &module), diagnostics)
+    }
+
+    fn build(source: &str, module: &Module) -> SyntaxTree {
+        let builder = Builder {
+            source,
+            lines: LineIndex::new(source),
+        };
+        let items = module
+            .items
+            .iter()
+            .filter_map(|item| builder.item(item))
+            .collect();
+        SyntaxTree {
+            nodes: builder.fill(0, source.len(), items),
+        }
+    }
+
+    pub fn find(&self, id: &str) -> Option<&SyntaxNode> {
+        self.nodes.iter().find_map(|node| node.find(id))
+    }
+
+    pub fn find_mut(&mut self, id: &str) -> Option<&mut SyntaxNode> {
+        self.nodes.iter_mut().find_map(|node| node.find_mut(id))
+    }
+
+    // parses the (edited) text of the tree
+    pub fn module(&self) -> Result<Module, ParseError> {
+        Module::parse(&self.to_string())
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for node in &self.nodes {
+            write!(f, "{}", node)?;
+        }
+        Ok(())
+    }
+}
+
+struct Builder<'a> {
+    source: &'a str,
+    lines: LineIndex,
+}
+
+impl Builder<'_> {
+    fn span(&self, start: usize, end: usize) -> Span {
+        let (line, column) = self.lines.line_column(self.source, start);
+        Span {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    fn leaf(&self, kind: SyntaxKind, start: usize, end: usize) -> SyntaxNode {
+        SyntaxNode {
+            span: self.span(start, end),
+            ..SyntaxNode::new(kind, &self.source[start..end])
+        }
+    }
+
+    // splits the text between start and end into whitespace, comment and
+    // text leaves.
+    fn tokens(&self, start: usize, end: usize, nodes: &mut Vec<SyntaxNode>) {
+        let mut offset = start;
+        while offset < end {
+            let rest = &self.source[offset..end];
+            let (kind, len) = if rest.starts_with(char::is_whitespace) {
+                let len = rest.find(|c: char| !c.is_whitespace());
+                (SyntaxKind::Whitespace, len.unwrap_or(rest.len()))
+            } else if rest.starts_with("//") {
+                (SyntaxKind::Comment, rest.find('\n').unwrap_or(rest.len()))
+            } else {
+                let len = [rest.find('\n'), rest.find("//")]
+                    .into_iter()
+                    .flatten()
+                    .min();
+                (SyntaxKind::Text, len.unwrap_or(rest.len()))
+            };
+            nodes.push(self.leaf(kind, offset, offset + len));
+            offset += len;
+        }
+    }
+
+    // the nodes in order with the text around them between start and end
+    // filled in.  A node without a usable span is dropped, its text ends up
+    // in a Text leaf.
+    fn fill(&self, start: usize, end: usize, nodes: Vec<SyntaxNode>) -> Vec<SyntaxNode> {
+        let mut filled = Vec::new();
+        let mut offset = start;
+        for node in nodes {
+            if node.span.line == 0 || node.span.start < offset || node.span.end > end {
+                continue;
+            }
+            self.tokens(offset, node.span.start, &mut filled);
+            offset = node.span.end;
+            filled.push(node);
+        }
+        self.tokens(offset, end, &mut filled);
+        filled
+    }
+
+    fn question(&self, question: &Question) -> Option<SyntaxNode> {
+        let span = question.span;
+        // the header runs to the ] after the header span
+        let header_end = question.header_span.end + 1;
+        if span.line == 0 || self.source.get(header_end - 1..header_end) != Some("]") {
+            return None;
+        }
+        // comment lines after the markdown go with the next item
+        let mut end = span.end;
+        loop {
+            let markdown = self.source[header_end..end].trim_end();
+            let line_start = markdown.rfind('\n').map_or(0, |indx| indx + 1);
+            if !markdown[line_start..].trim_start().starts_with("//") {
+                end = header_end + markdown.len();
+                break;
+            }
+            end = header_end + line_start;
+        }
+        let mut children = vec![self.leaf(SyntaxKind::Header, span.start, header_end)];
+        children.extend(self.fill(header_end, end, Vec::new()));
+        Some(SyntaxNode {
+            kind: SyntaxKind::Question,
+            id: Some(String::from(question.id())),
+            span: self.span(span.start, end),
+            text: String::new(),
+            children,
+        })
+    }
+
+    fn block(
+        &self,
+        kind: SyntaxKind,
+        id: Option<&str>,
+        (span, open, close): (Span, Span, Span),
+        inner: Vec<SyntaxNode>,
+    ) -> Option<SyntaxNode> {
+        if [span, open, close].iter().any(|span| span.line == 0) {
+            return None;
+        }
+        let mut children = vec![self.leaf(SyntaxKind::OpenTag, open.start, open.end)];
+        children.extend(self.fill(open.end, close.start, inner));
+        children.push(self.leaf(SyntaxKind::CloseTag, close.start, close.end));
+        Some(SyntaxNode {
+            kind,
+            id: id.map(String::from),
+            span,
+            text: String::new(),
+            children,
+        })
+    }
+
+    fn item(&self, item: &ModuleItem) -> Option<SyntaxNode> {
+        match item {
+            ModuleItem::Question(q) => self.question(q),
+            ModuleItem::Loop(l) => {
+                let inner = l.questions().iter().filter_map(|q| self.item(q)).collect();
+                let spans = (l.span, l.tag().span, l.close_span);
+                self.block(SyntaxKind::Loop, l.id.as_deref(), spans, inner)
+            }
+            ModuleItem::Grid(g) => {
+                let inner = g.rows.iter().filter_map(|q| self.question(q)).collect();
+                let spans = (g.span, g.tag().span, g.close_span);
+                self.block(SyntaxKind::Grid, g.id.as_deref(), spans, inner)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULE: &str = "// Module 1\n\n[Q1] Do you smoke?\n  (1) Yes\n  (0) No -> Q3   \n// ask everyone\n\n<loop max=2>\n\t[L1] loop question // why?\n</loop>\n<grid id=G1>\n(1) yes\n[G1_1] row\n</grid>\n[Q3] The end";
+
+    #[test]
+    fn test_lossless_round_trip() {
+        let tree = SyntaxTree::parse(MODULE).unwrap();
+        assert_eq!(tree.to_string(), MODULE);
+
+        let kinds: Vec<_> = tree.nodes.iter().map(|node| node.kind).collect();
+        use SyntaxKind::*;
+        assert_eq!(
+            kinds,
+            vec![
+                Comment, Whitespace, Question, Whitespace, Comment, Whitespace, Loop, Whitespace,
+                Grid, Whitespace, Question
+            ]
+        );
+
+        let l1 = tree.find("L1").unwrap();
+        let kinds: Vec<_> = l1.children.iter().map(|node| node.kind).collect();
+        assert_eq!(kinds, vec![Header, Whitespace, Text, Comment]);
+        assert_eq!(l1.children[3].to_string(), "// why?");
+        assert_eq!((l1.span.line, l1.span.column), (9, 2));
+
+        let grid = tree.find("G1").unwrap();
+        assert_eq!(grid.children[0].to_string(), "<grid id=G1>");
+        assert_eq!(grid.children[2].to_string(), "(1) yes");
+        assert_eq!(grid.children.last().unwrap().kind, CloseTag);
+    }
+
+    #[test]
+    fn test_edit_syntax_tree() {
+        let mut tree = SyntaxTree::parse(MODULE).unwrap();
+        tree.find_mut("L1").unwrap().children[0].set_text("[L1 min=1]");
+        tree.find_mut("G1_1").unwrap().set_text("");
+        assert_eq!(
+            tree.to_string(),
+            MODULE
+                .replace("[L1]", "[L1 min=1]")
+                .replace("[G1_1] row", "")
+        );
+        let module = tree.module().unwrap();
+        assert_eq!(module.items.len(), 4);
+
+        // text the parser skipped is kept as it was
+        let source = "[Q1] one\n<loop>oops [L1] a\n</loop>\n  [Q2] two\n";
+        let (tree, diagnostics) = SyntaxTree::parse_recovering(source);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(tree.to_string(), source);
+    }
+}
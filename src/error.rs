@@ -0,0 +1,99 @@
+use crate::span::line_column;
+use std::fmt;
+
+// An error in a module with enough position information to point at it:
+//
+// unterminated <loop> opened at line 3, column 1
+//   3 | <loop max=5>
+//     | ^
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParseError {
+    // byte offset into the module text
+    pub offset: usize,
+    // 1-based line and column (in characters)
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    // the offending line with a caret under the column
+    pub snippet: String,
+}
+
+impl ParseError {
+    pub fn new(source: &str, offset: usize, message: impl Into<String>) -> Self {
+        let mut offset = offset.min(source.len());
+        while !source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let (line, _) = line_column(source, offset);
+        ParseError::on_line(source, offset, line, message)
+    }
+
+    // An error at offset, which is on line.  Only the text of that line is
+    // looked at, for callers that keep count of the lines as they go.
+    pub(crate) fn on_line(
+        source: &str,
+        offset: usize,
+        line: usize,
+        message: impl Into<String>,
+    ) -> Self {
+        let line_start = source[..offset].rfind('\n').map_or(0, |indx| indx + 1);
+        let line_end = source[offset..]
+            .find('\n')
+            .map_or(source.len(), |indx| offset + indx);
+        let column = source[line_start..offset].chars().count() + 1;
+        let text = source[line_start..line_end].trim_end_matches('\r');
+        let gutter = line.to_string();
+        let snippet = format!(
+            "{} | {}\n{} | {}^",
+            gutter,
+            text,
+            " ".repeat(gutter.len()),
+            " ".repeat(column - 1)
+        );
+
+        ParseError {
+            offset,
+            line,
+            column,
+            message: message.into(),
+            snippet,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}\n{}",
+            self.message, self.line, self.column, self.snippet
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_error_position() {
+        let source = "[Q1] one\n  [Q2] two\n";
+        let e = ParseError::new(source, 11, "oops");
+        assert_eq!((e.line, e.column), (2, 3));
+        assert_eq!(e.snippet, "2 |   [Q2] two\n  |   ^");
+        assert_eq!(
+            e.to_string(),
+            "oops at line 2, column 3\n2 |   [Q2] two\n  |   ^"
+        );
+
+        // columns count characters, not bytes.
+        let e = ParseError::new("¿Qué? <loop>", 8, "unterminated <loop>");
+        assert_eq!((e.line, e.column), (1, 7));
+
+        let e = ParseError::new("abc", 3, "unexpected end");
+        assert_eq!((e.line, e.column), (1, 4));
+    }
+}
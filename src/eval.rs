@@ -0,0 +1,330 @@
+use crate::Expr;
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Undefined,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    // the selected values of a checkbox question
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Undefined => false,
+            Value::Bool(b) => *b,
+            Value::Number(n) => *n != 0. && !n.is_nan(),
+            Value::Str(s) => !s.is_empty() && s != "false",
+            Value::Array(values) => !values.is_empty(),
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Str(s) => s.trim().parse().ok(),
+            Value::Bool(b) => Some(if *b { 1. } else { 0. }),
+            _ => None,
+        }
+    }
+
+    fn is_defined(&self) -> bool {
+        match self {
+            Value::Undefined => false,
+            Value::Str(s) => !s.is_empty(),
+            Value::Array(values) => !values.is_empty(),
+            _ => true,
+        }
+    }
+
+    // "1" == 1, and a checkbox answer equals any of its selected values.
+    pub(crate) fn loosely_equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Undefined, _) | (_, Value::Undefined) => false,
+            (Value::Array(values), other) => values.iter().any(|v| v.loosely_equals(other)),
+            (value, Value::Array(others)) => others.iter().any(|o| value.loosely_equals(o)),
+            (a, b) => match (a.as_number(), b.as_number()) {
+                (Some(x), Some(y)) => x == y,
+                _ => a.to_string() == b.to_string(),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Undefined => write!(f, ""),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Array(values) => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                write!(f, "{}", values.join(","))
+            }
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Str(String::from(value))
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+// The answers a participant has given, keyed by question (or input) id.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ResponseStore {
+    values: HashMap<String, Value>,
+}
+
+impl ResponseStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, id: &str, value: impl Into<Value>) {
+        self.values.insert(String::from(id), value.into());
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Value> {
+        self.values.get(id)
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<Value> {
+        self.values.remove(id)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.get(id).is_some_and(|v| v.is_defined())
+    }
+}
+
+// Like the Quest renderer, an identifier that is not in the store is
+// treated as a literal, so equals(Q1,yes) compares Q1 to "yes".
+fn resolve(expr: &Expr, store: &ResponseStore) -> Value {
+    match expr {
+        Expr::Ident(id) => match store.get(id) {
+            Some(value) => value.clone(),
+            None => Value::Str(id.clone()),
+        },
+        _ => evaluate(expr, store),
+    }
+}
+
+// For the existence functions an identifier always means a question id.
+fn exists(expr: &Expr, store: &ResponseStore) -> bool {
+    match expr {
+        Expr::Ident(id) => store.contains(id),
+        _ => evaluate(expr, store).is_defined(),
+    }
+}
+
+fn lookup(expr: &Expr, store: &ResponseStore) -> Value {
+    match expr {
+        Expr::Ident(id) => store.get(id).cloned().unwrap_or(Value::Undefined),
+        _ => evaluate(expr, store),
+    }
+}
+
+fn compare(args: &[Expr], store: &ResponseStore, cmp: fn(f64, f64) -> bool) -> Value {
+    let numbers: Option<Vec<f64>> = args
+        .iter()
+        .map(|arg| resolve(arg, store).as_number())
+        .collect();
+    match numbers.as_deref() {
+        Some([x, y]) => Value::Bool(cmp(*x, *y)),
+        _ => Value::Bool(false),
+    }
+}
+
+fn numbers(args: &[Expr], store: &ResponseStore) -> Vec<f64> {
+    args.iter()
+        .filter_map(|arg| resolve(arg, store).as_number())
+        .collect()
+}
+
+// dates are compared as YYYY-MM-DD (or YYYY-MM) strings
+fn parse_date(value: &Value) -> Option<(i32, u32, u32)> {
+    let value = value.to_string();
+    let mut parts = value.trim().splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next().map(|d| d.parse().ok()).unwrap_or(Some(1))?;
+    Some((year, month, day))
+}
+
+fn call(name: &str, args: &[Expr], store: &ResponseStore) -> Value {
+    let arg = |indx: usize| {
+        args.get(indx)
+            .map(|arg| resolve(arg, store))
+            .unwrap_or(Value::Undefined)
+    };
+    match name {
+        "and" => Value::Bool(args.iter().all(|a| evaluate(a, store).is_truthy())),
+        "or" => Value::Bool(args.iter().any(|a| evaluate(a, store).is_truthy())),
+        // an unanswered question is false, not its id as a string
+        "not" => Value::Bool(!args.first().is_some_and(|a| lookup(a, store).is_truthy())),
+        "equals" => Value::Bool(arg(0).loosely_equals(&arg(1))),
+        "doesNotEqual" => Value::Bool(!arg(0).loosely_equals(&arg(1))),
+        "greaterThan" => compare(args, store, |x, y| x > y),
+        "greaterThanOrEqual" => compare(args, store, |x, y| x >= y),
+        "lessThan" => compare(args, store, |x, y| x < y),
+        "lessThanOrEqual" => compare(args, store, |x, y| x <= y),
+        "isDefined" | "exists" => Value::Bool(args.first().is_some_and(|a| exists(a, store))),
+        "isNotDefined" | "doesNotExist" => {
+            Value::Bool(!args.first().is_some_and(|a| exists(a, store)))
+        }
+        "noneExist" => Value::Bool(!args.iter().any(|a| exists(a, store))),
+        "someExist" => Value::Bool(args.iter().any(|a| exists(a, store))),
+        "allExist" => Value::Bool(args.iter().all(|a| exists(a, store))),
+        "valueEquals" => Value::Bool(
+            args.first()
+                .is_some_and(|a| lookup(a, store).loosely_equals(&arg(1))),
+        ),
+        "valueIsOneOf" => {
+            let value = args
+                .first()
+                .map(|a| lookup(a, store))
+                .unwrap_or(Value::Undefined);
+            Value::Bool((1..args.len()).any(|indx| value.loosely_equals(&arg(indx))))
+        }
+        "valueIsBetween" => {
+            let value = args.first().and_then(|a| lookup(a, store).as_number());
+            match (value, arg(1).as_number(), arg(2).as_number()) {
+                (Some(v), Some(lo), Some(hi)) => Value::Bool(lo <= v && v <= hi),
+                _ => Value::Bool(false),
+            }
+        }
+        "valueOrDefault" => match args.first().map(|a| lookup(a, store)) {
+            Some(value) if value.is_defined() => value,
+            _ => arg(1),
+        },
+        "numberOfChoicesSelected" => match args.first().map(|a| lookup(a, store)) {
+            Some(Value::Array(values)) => Value::Number(values.len() as f64),
+            Some(value) if value.is_defined() => Value::Number(1.),
+            _ => Value::Number(0.),
+        },
+        "min" => numbers(args, store)
+            .into_iter()
+            .reduce(f64::min)
+            .map(Value::Number)
+            .unwrap_or(Value::Undefined),
+        "max" => numbers(args, store)
+            .into_iter()
+            .reduce(f64::max)
+            .map(Value::Number)
+            .unwrap_or(Value::Undefined),
+        "sum" => Value::Number(numbers(args, store).iter().sum()),
+        "difference" => match (arg(0).as_number(), arg(1).as_number()) {
+            (Some(x), Some(y)) => Value::Number(x - y),
+            _ => Value::Undefined,
+        },
+        // -1, 0 or 1 as the first date is before, on or after the second.
+        "dateCompare" => match (parse_date(&arg(0)), parse_date(&arg(1))) {
+            (Some(d1), Some(d2)) => Value::Number(d1.cmp(&d2) as i32 as f64),
+            _ => Value::Undefined,
+        },
+        // the difference as a percentage of the mean of the two values
+        "percentDiff" => match (arg(0).as_number(), arg(1).as_number()) {
+            (Some(x), Some(y)) if x.abs() + y.abs() != 0. => {
+                Value::Number((x - y).abs() / ((x.abs() + y.abs()) / 2.) * 100.)
+            }
+            _ => Value::Undefined,
+        },
+        _ => Value::Undefined,
+    }
+}
+
+pub fn evaluate(expr: &Expr, store: &ResponseStore) -> Value {
+    match expr {
+        Expr::Call { name, args } => call(name, args, store),
+        Expr::Ident(_) => resolve(expr, store),
+        Expr::Number(n) => Value::Number(*n),
+        Expr::Str(s) => Value::Str(s.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+
+    fn eval(expr: &str, store: &ResponseStore) -> Value {
+        evaluate(&parse_expr(expr).unwrap(), store)
+    }
+
+    #[test]
+    fn test_evaluate_logic() {
+        let mut store = ResponseStore::new();
+        store.set("Q1", "1");
+        store.set("Q2", 25.);
+        store.set("CB", Value::Array(vec![Value::from("3"), Value::from("4")]));
+
+        assert_eq!(eval("equals(Q1,1)", &store), Value::Bool(true));
+        assert_eq!(eval("equals(Q1,yes)", &store), Value::Bool(false));
+        assert_eq!(eval("doesNotEqual(Q1,2)", &store), Value::Bool(true));
+        assert_eq!(eval("equals(CB,4)", &store), Value::Bool(true));
+        assert_eq!(eval("greaterThan(Q2,18)", &store), Value::Bool(true));
+        assert_eq!(eval("lessThan(Q2,Q1)", &store), Value::Bool(false));
+        assert_eq!(eval("lessThan(Q3,1)", &store), Value::Bool(false));
+        assert_eq!(
+            eval(
+                "and(equals(Q1,1),or(isDefined(Q3),not(isDefined(Q3))))",
+                &store
+            ),
+            Value::Bool(true)
+        );
+        assert_eq!(eval("not(Q3)", &store), Value::Bool(true));
+        assert_eq!(eval("not(Q1)", &store), Value::Bool(false));
+        assert_eq!(eval("not(equals(Q1,2))", &store), Value::Bool(true));
+        assert_eq!(eval("doesNotExist(Q3)", &store), Value::Bool(true));
+        assert_eq!(eval("someExist(Q3,Q2)", &store), Value::Bool(true));
+        assert_eq!(eval("allExist(Q3,Q2)", &store), Value::Bool(false));
+        assert_eq!(eval("noneExist(Q3,Q4)", &store), Value::Bool(true));
+        assert_eq!(eval("valueIsOneOf(Q1,3,2,1)", &store), Value::Bool(true));
+        assert_eq!(eval("valueIsBetween(Q2,18,30)", &store), Value::Bool(true));
+        assert_eq!(
+            eval("numberOfChoicesSelected(CB)", &store),
+            Value::Number(2.)
+        );
+    }
+
+    #[test]
+    fn test_evaluate_values() {
+        let mut store = ResponseStore::new();
+        store.set("AGE", "40");
+        store.set("D1", "2024-01-15");
+        store.set("D2", "2024-03");
+
+        assert_eq!(eval("valueOrDefault(AGE,0)", &store), Value::from("40"));
+        assert_eq!(eval("valueOrDefault(WEIGHT,0)", &store), Value::Number(0.));
+        assert_eq!(eval("min(AGE,12,WEIGHT)", &store), Value::Number(12.));
+        assert_eq!(eval("max(AGE,12)", &store), Value::Number(40.));
+        assert_eq!(eval("dateCompare(D1,D2)", &store), Value::Number(-1.));
+        assert_eq!(
+            eval("dateCompare(D1,'2024-01-15')", &store),
+            Value::Number(0.)
+        );
+        assert_eq!(eval("percentDiff(110,90)", &store), Value::Number(20.));
+        // |x| + |y| is the denominator, x + y == 0 is fine
+        assert_eq!(eval("percentDiff(5,-5)", &store), Value::Number(200.));
+        assert_eq!(eval("percentDiff(0,0)", &store), Value::Undefined);
+        assert_eq!(eval("unknownFunction(AGE)", &store), Value::Undefined);
+    }
+}
@@ -0,0 +1,200 @@
+use nom::branch::alt;
+use nom::bytes::complete::{take_until, take_while1};
+use nom::character::complete::{char, multispace0};
+use nom::combinator::{cut, map, not, opt, peek, recognize, verify};
+use nom::error::{context, VerboseError, VerboseErrorKind};
+use nom::multi::separated_list0;
+use nom::number::complete::recognize_float;
+use nom::sequence::{delimited, preceded, terminated, tuple};
+use nom::IResult;
+use std::fmt;
+
+// The AST of a displayif (or min/max) expression such as
+// and(equals(Q1,1),not(isDefined(Q2)))
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    Call { name: String, args: Vec<Expr> },
+    // a question id, or a bare word such as yes
+    Ident(String),
+    Number(f64),
+    // a quoted string
+    Str(String),
+}
+
+impl Expr {
+    pub fn call(name: &str, args: Vec<Expr>) -> Self {
+        Expr::Call {
+            name: String::from(name),
+            args,
+        }
+    }
+
+    pub fn ident(name: &str) -> Self {
+        Expr::Ident(String::from(name))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExprError {
+    // byte offset into the expression text
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+type ExprResult<'a, T> = IResult<&'a str, T, VerboseError<&'a str>>;
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '#' || c == '.'
+}
+
+fn ws<'a, T>(
+    parser: impl FnMut(&'a str) -> ExprResult<'a, T>,
+) -> impl FnMut(&'a str) -> ExprResult<'a, T> {
+    delimited(multispace0, parser, multispace0)
+}
+
+fn string_literal(input: &str) -> ExprResult<'_, Expr> {
+    let double = delimited(char('"'), take_until("\""), char('"'));
+    let single = delimited(char('\''), take_until("'"), char('\''));
+    map(alt((double, single)), |s: &str| Expr::Str(String::from(s)))(input)
+}
+
+// 12abc is an identifier, not the number 12 followed by garbage.
+fn number(input: &str) -> ExprResult<'_, Expr> {
+    let (input, number) = terminated(
+        verify(recognize_float, |s: &str| s.parse::<f64>().is_ok()),
+        not(peek(verify(nom::character::complete::anychar, |c| {
+            is_ident_char(*c)
+        }))),
+    )(input)?;
+    Ok((input, Expr::Number(number.parse().unwrap())))
+}
+
+fn identifier(input: &str) -> ExprResult<'_, &str> {
+    context(
+        "expected an identifier",
+        recognize(take_while1(is_ident_char)),
+    )(input)
+}
+
+fn arguments(input: &str) -> ExprResult<'_, Vec<Expr>> {
+    preceded(
+        char('('),
+        cut(terminated(
+            separated_list0(char(','), expression),
+            context("expected ',' or ')'", ws(char(')'))),
+        )),
+    )(input)
+}
+
+fn call_or_identifier(input: &str) -> ExprResult<'_, Expr> {
+    let (input, (name, args)) = tuple((identifier, opt(preceded(multispace0, arguments))))(input)?;
+    let expr = match args {
+        Some(args) => Expr::call(name, args),
+        None => Expr::ident(name),
+    };
+    Ok((input, expr))
+}
+
+fn expression(input: &str) -> ExprResult<'_, Expr> {
+    context(
+        "expected an expression",
+        ws(alt((string_literal, number, call_or_identifier))),
+    )(input)
+}
+
+pub fn parse_expr(input: &str) -> Result<Expr, ExprError> {
+    let error = |rest: &str, message: &str| ExprError {
+        offset: input.len() - rest.len(),
+        message: String::from(message),
+    };
+    match expression(input) {
+        Ok(("", expr)) => Ok(expr),
+        Ok((rest, _)) => Err(error(rest, "unexpected input after the expression")),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+            // the first entry is where parsing actually stopped, the first
+            // context is the innermost description of what was expected.
+            let rest = e.errors.first().map(|(rest, _)| *rest).unwrap_or(input);
+            let message = e
+                .errors
+                .iter()
+                .find_map(|(_, kind)| match kind {
+                    VerboseErrorKind::Context(ctx) => Some(*ctx),
+                    _ => None,
+                })
+                .unwrap_or("invalid expression");
+            Err(error(rest, message))
+        }
+        Err(nom::Err::Incomplete(_)) => Err(error("", "incomplete expression")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_literals() {
+        assert_eq!(parse_expr("12"), Ok(Expr::Number(12.)));
+        assert_eq!(parse_expr(" -1.5 "), Ok(Expr::Number(-1.5)));
+        assert_eq!(parse_expr("'a b'"), Ok(Expr::Str(String::from("a b"))));
+        assert_eq!(parse_expr(r#""x,y""#), Ok(Expr::Str(String::from("x,y"))));
+        assert_eq!(parse_expr("D_123"), Ok(Expr::ident("D_123")));
+        assert_eq!(parse_expr("12abc"), Ok(Expr::ident("12abc")));
+        assert_eq!(parse_expr("Q1_#loop"), Ok(Expr::ident("Q1_#loop")));
+    }
+
+    #[test]
+    fn test_parse_calls() {
+        assert_eq!(
+            parse_expr("equals(Q1,1)"),
+            Ok(Expr::call(
+                "equals",
+                vec![Expr::ident("Q1"), Expr::Number(1.)]
+            ))
+        );
+        assert_eq!(
+            parse_expr("and(equals(Q1, 'yes'), not(isDefined(Q2)))"),
+            Ok(Expr::call(
+                "and",
+                vec![
+                    Expr::call(
+                        "equals",
+                        vec![Expr::ident("Q1"), Expr::Str(String::from("yes"))]
+                    ),
+                    Expr::call(
+                        "not",
+                        vec![Expr::call("isDefined", vec![Expr::ident("Q2")])]
+                    ),
+                ]
+            ))
+        );
+        assert_eq!(
+            parse_expr("noneExist()"),
+            Ok(Expr::call("noneExist", vec![]))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let e = parse_expr("equals(Q1,1").unwrap_err();
+        assert_eq!(e.offset, 11);
+        assert_eq!(e.message, "expected ',' or ')'");
+
+        let e = parse_expr("equals(Q1,1) x").unwrap_err();
+        assert_eq!(e.offset, 13);
+
+        let e = parse_expr("or(equals(Q1,1),,Q2)").unwrap_err();
+        assert_eq!(e.offset, 15);
+
+        assert!(parse_expr("").is_err());
+    }
+}
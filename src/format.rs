@@ -0,0 +1,372 @@
+use crate::cst::{SyntaxKind, SyntaxNode, SyntaxTree};
+use crate::{
+    parse_attributes, parse_header, parse_no_response, parse_response, parse_tag_parts,
+    take_until_next_module_item, write_attributes, Grid, ParseError, Question, Response,
+    ResponseKind, Tag,
+};
+use std::collections::BTreeMap;
+use std::fmt;
+
+const INDENT: &str = "  ";
+
+// Writes a module back out in one canonical layout so that two people
+// editing the same module only produce diffs for what they changed:
+//
+// [Q1 displayif=equals(Q0,1)] Do you smoke?
+// (1)  Yes
+// (2)  No -> Q3
+// (77) Don't know
+//
+// <loop id=L max=5>
+//   [L1] loop question
+// </loop>
+//
+// Headers and tags have their attributes sorted with single spaces and
+// quotes only where they are needed, lines are trimmed, runs of blank lines
+// become one, the labels of a response list line up and every level of
+// <loop> or <grid> is indented by two spaces.  Questions are separated by a
+// blank line, except for the rows of a grid.
+//
+// Formatting works on the lossless SyntaxTree so comments between items and
+// inside loops are kept, each on a line of its own at the indent of the
+// items around it, with a blank line before it only if there was one.  A
+// module the formatter would drop text from, e.g. a header with text the
+// parser does not understand, is an error rather than formatted.
+//
+// It takes the source rather than a parsed Module because a Module has
+// already dropped the comments and the blank lines between items, so a
+// format_module(&Module) could only give them back by losing them.
+pub fn format_module(source: &str) -> Result<String, ParseError> {
+    let tree = SyntaxTree::parse(source)?;
+    check_headers(source, &tree.nodes)?;
+
+    let mut out = String::new();
+    let first = tree
+        .nodes
+        .iter()
+        .position(is_item)
+        .unwrap_or(tree.nodes.len());
+    let preamble: String = tree.nodes[..first]
+        .iter()
+        .map(SyntaxNode::to_string)
+        .collect();
+    let preamble = preamble.trim();
+    if !preamble.is_empty() {
+        format_lines(preamble, "", &mut out);
+        out.push('\n');
+    }
+    format_nodes(source, &tree.nodes[first..], "", &mut out)?;
+
+    // nothing the formatter does should add, drop or move a comment
+    let formatted = SyntaxTree::parse(&out).map_err(|e| {
+        ParseError::new(
+            source,
+            0,
+            format!("formatting broke the module: {}", e.message),
+        )
+    })?;
+    if comments(&formatted.nodes) != comments(&tree.nodes) {
+        return Err(ParseError::new(
+            source,
+            0,
+            "formatting would lose a comment",
+        ));
+    }
+    Ok(out)
+}
+
+fn is_item(node: &SyntaxNode) -> bool {
+    matches!(
+        node.kind,
+        SyntaxKind::Question | SyntaxKind::Loop | SyntaxKind::Grid
+    )
+}
+
+fn comments(nodes: &[SyntaxNode]) -> Vec<String> {
+    let mut found = Vec::new();
+    for node in nodes {
+        if node.kind == SyntaxKind::Comment {
+            found.push(String::from(node.to_string().trim()));
+        }
+        found.extend(comments(&node.children));
+    }
+    found
+}
+
+// headers that only parse in part, formatting would drop the rest
+fn check_headers(source: &str, nodes: &[SyntaxNode]) -> Result<(), ParseError> {
+    for node in nodes {
+        if node.kind == SyntaxKind::Header {
+            let text = node.to_string();
+            let inner = text.trim_start_matches('[').trim_end_matches(']');
+            if !matches!(parse_header(inner), Ok((rest, _)) if rest.trim().is_empty()) {
+                let message = format!(
+                    "cannot format {}, part of the header is not understood",
+                    text
+                );
+                return Err(ParseError::new(source, node.span.start, message));
+            }
+        }
+        check_headers(source, &node.children)?;
+    }
+    Ok(())
+}
+
+struct Attributes<'a>(&'a BTreeMap<String, String>);
+
+impl fmt::Display for Attributes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_attributes(f, self.0)
+    }
+}
+
+// the items and comments of nodes, items separated by a blank line.
+fn format_nodes(
+    source: &str,
+    nodes: &[SyntaxNode],
+    indent: &str,
+    out: &mut String,
+) -> Result<(), ParseError> {
+    // whether the last thing written was an item, None at the start
+    let mut previous: Option<bool> = None;
+    let mut blank_line = false;
+    for node in nodes {
+        let item = is_item(node);
+        match node.kind {
+            SyntaxKind::Whitespace => {
+                blank_line |= node.to_string().matches('\n').count() > 1;
+                continue;
+            }
+            SyntaxKind::Comment | SyntaxKind::Question | SyntaxKind::Loop | SyntaxKind::Grid => {
+                if previous.is_some_and(|previous| blank_line || (previous && item)) {
+                    out.push('\n');
+                }
+            }
+            _ => {
+                let message = format!("cannot format {:?}", node.to_string());
+                return Err(ParseError::new(source, node.span.start, message));
+            }
+        }
+        match node.kind {
+            SyntaxKind::Comment => {
+                out.push_str(&format!("{}{}\n", indent, node.to_string().trim()))
+            }
+            SyntaxKind::Question => format_question(&question(node), indent, out),
+            SyntaxKind::Loop => format_loop(source, node, indent, out)?,
+            _ => format_grid(&grid(node), indent, out),
+        }
+        previous = Some(item);
+        blank_line = false;
+    }
+    Ok(())
+}
+
+fn question(node: &SyntaxNode) -> Question {
+    let header = node.children[0].to_string();
+    let markdown: String = node.children[1..]
+        .iter()
+        .map(SyntaxNode::to_string)
+        .collect();
+    Question::new(&header[1..header.len() - 1], &markdown)
+}
+
+fn tag(node: &SyntaxNode) -> Tag {
+    let text = node.children[0].to_string();
+    let (name, params) = parse_tag_parts(&text).map_or(("", ""), |(_, parts)| parts);
+    Tag::new(name, params)
+}
+
+fn grid(node: &SyntaxNode) -> Grid {
+    let inner = &node.children[1..node.children.len() - 1];
+    let markdown: String = inner.iter().map(SyntaxNode::to_string).collect();
+    Grid::new(tag(node), &markdown)
+}
+
+// <name attributes>, params that are not all attributes are kept as they are.
+fn format_tag(tag: &Tag) -> String {
+    match parse_attributes(&tag.params) {
+        Ok(("", attributes)) => format!("<{}{}>", tag.name, Attributes(&attributes)),
+        _ => format!("<{} {}>", tag.name, tag.params),
+    }
+}
+
+fn format_loop(
+    source: &str,
+    node: &SyntaxNode,
+    indent: &str,
+    out: &mut String,
+) -> Result<(), ParseError> {
+    let inner = &node.children[1..node.children.len() - 1];
+    out.push_str(&format!("{}{}\n", indent, format_tag(&tag(node))));
+    format_nodes(source, inner, &format!("{}{}", indent, INDENT), out)?;
+    out.push_str(&format!("{}</loop>\n", indent));
+    Ok(())
+}
+
+fn format_grid(g: &Grid, indent: &str, out: &mut String) {
+    let inner = format!("{}{}", indent, INDENT);
+    out.push_str(&format!("{}{}\n", indent, format_tag(g.tag())));
+    // the prompt and columns before the first row
+    if let Ok((_, preamble)) = take_until_next_module_item(g.markdown()) {
+        format_lines(preamble, &inner, out);
+    }
+    for row in &g.rows {
+        format_question(row, &inner, out);
+    }
+    out.push_str(&format!("{}</grid>\n", indent));
+}
+
+fn response_marker(response: &Response) -> String {
+    let (open, close) = match response.kind {
+        ResponseKind::Radio => ('(', ')'),
+        ResponseKind::Checkbox => ('[', ']'),
+    };
+    format!(
+        "{}{}{}{}",
+        open,
+        response.value,
+        Attributes(&response.attributes),
+        close
+    )
+}
+
+fn is_body_line(line: &str) -> bool {
+    parse_response(line).is_err() && parse_no_response(line).is_err()
+}
+
+fn format_question(question: &Question, indent: &str, out: &mut String) {
+    out.push_str(&format!("{}[{}]", indent, question.header));
+    // the first line of text stays next to the header
+    let markdown = question.markdown.trim();
+    let (first, rest) = markdown.split_once('\n').unwrap_or((markdown, ""));
+    if !first.is_empty() && is_body_line(first) {
+        out.push_str(&format!(" {}\n", first.trim()));
+        format_lines(rest, indent, out);
+    } else {
+        out.push('\n');
+        format_lines(markdown, indent, out);
+    }
+}
+
+// trimmed lines at indent, blank lines collapsed and responses aligned.
+fn format_lines(text: &str, indent: &str, out: &mut String) {
+    let width = text
+        .lines()
+        .filter_map(|line| parse_response(line).ok())
+        .map(|(_, response)| response_marker(&response).chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line = match (parse_response(line), parse_no_response(line)) {
+            (Ok((_, response)), _) => {
+                let mut line = format!("{:width$} {}", response_marker(&response), response.label);
+                if let Some(goto) = &response.goto {
+                    line.push_str(&format!(" -> {}", goto));
+                }
+                String::from(line.trim_end())
+            }
+            (_, Ok((_, target))) => format!("#NR -> {}", target),
+            _ => String::from(line.trim()),
+        };
+        if !line.is_empty() || lines.last().is_some_and(|last| !last.is_empty()) {
+            lines.push(line);
+        }
+    }
+    while lines.last().is_some_and(|last| last.is_empty()) {
+        lines.pop();
+    }
+    for line in lines {
+        if line.is_empty() {
+            out.push('\n');
+        } else {
+            out.push_str(&format!("{}{}\n", indent, line));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Module;
+
+    #[test]
+    fn test_format_module() {
+        let markdown = "  Module 1  \n\n\n[Q1    min=0   displayif=\"equals(Q0,1)\"]   Do you smoke?   \n (1)Yes\n(77)   Don't know->Q3\n\n\n#NR->Q3\n[Q2]\n(1) a\n<loop   max=2 id=L>\n[L1] in a loop\n  [L2] second\n</loop>\n<grid id=G1>\n   How much?\n[1] little\n[22] a lot\n[G1_1]    row 1\n[G1_2] row 2\n</grid>\n[Q3!] The end";
+        let formatted = format_module(markdown).unwrap();
+        assert_eq!(
+            formatted,
+            "Module 1\n\
+             \n\
+             [Q1 displayif=equals(Q0,1) min=0] Do you smoke?\n\
+             (1)  Yes\n\
+             (77) Don't know -> Q3\n\
+             \n\
+             #NR -> Q3\n\
+             \n\
+             [Q2]\n\
+             (1) a\n\
+             \n\
+             <loop id=L max=2>\n\
+             \x20 [L1] in a loop\n\
+             \n\
+             \x20 [L2] second\n\
+             </loop>\n\
+             \n\
+             <grid id=G1>\n\
+             \x20 How much?\n\
+             \x20 [1]  little\n\
+             \x20 [22] a lot\n\
+             \x20 [G1_1] row 1\n\
+             \x20 [G1_2] row 2\n\
+             </grid>\n\
+             \n\
+             [Q3!] The end\n"
+        );
+
+        // formatting is stable and does not change what the module means
+        assert_eq!(format_module(&formatted).unwrap(), formatted);
+        let module = Module::parse(markdown).unwrap();
+        let reformatted = Module::parse(&formatted).unwrap();
+        let edges = |module: &Module| -> Vec<String> {
+            let graph = module.navigation_graph();
+            graph
+                .edges
+                .iter()
+                .map(|e| format!("{}>{}", e.from, e.to))
+                .collect()
+        };
+        assert_eq!(edges(&reformatted), edges(&module));
+    }
+
+    #[test]
+    fn test_format_keeps_comments() {
+        let markdown = "// Module 2\n[Q1] one\n// after Q1\n\n// before the loop\n<loop max=2>   // people\n// in the loop\n[L1] a\n\n// after L1\n</loop>\n// after the loop\n[Q2] two";
+        let formatted = format_module(markdown).unwrap();
+        assert_eq!(
+            formatted,
+            "// Module 2\n\
+             \n\
+             [Q1] one\n\
+             // after Q1\n\
+             \n\
+             // before the loop\n\
+             <loop max=2>\n\
+             \x20 // people\n\
+             \x20 // in the loop\n\
+             \x20 [L1] a\n\
+             \n\
+             \x20 // after L1\n\
+             </loop>\n\
+             // after the loop\n\
+             [Q2] two\n"
+        );
+        assert_eq!(format_module(&formatted).unwrap(), formatted);
+
+        // the text after =oops would be lost
+        let e = format_module("[Q0] zero\n[Q1 =oops foo] a").unwrap_err();
+        assert_eq!((e.line, e.column), (2, 1));
+        assert!(e.message.contains("[Q1 =oops foo]"));
+    }
+}
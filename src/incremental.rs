@@ -0,0 +1,271 @@
+use crate::{
+    parse_question_loop_grid, parse_whitespace_or_comment, resolve_local_spans,
+    take_until_next_module_item, Module, ModuleItem, ParseError,
+};
+use nom::multi::many0;
+use std::ops::Range;
+
+// Replaces the bytes in range of the old text with text.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl TextEdit {
+    pub fn new(range: Range<usize>, text: &str) -> Self {
+        TextEdit {
+            range,
+            text: String::from(text),
+        }
+    }
+
+    pub fn apply(&self, source: &str) -> String {
+        let mut edited = String::from(source);
+        edited.replace_range(self.range.clone(), &self.text);
+        edited
+    }
+}
+
+// the items of text, None unless all of it parses
+fn parse_items(text: &str) -> Option<Vec<ModuleItem>> {
+    let (rest, items) = many0(parse_question_loop_grid)(text).ok()?;
+    let (rest, _) = parse_whitespace_or_comment(rest).ok()?;
+    rest.is_empty().then_some(items)
+}
+
+impl Module {
+    // Parses source, which is the text this module was parsed from with
+    // edit applied, re-using everything the edit cannot have changed.
+    //
+    // The item the edit starts in is parsed again, and so is the item before
+    // it since an edit to the [ID or <loop of the one after can move where
+    // it ends, e.g. [Q3] changed to [q3] is no longer a question.  When the
+    // edit starts in the first item the preamble is parsed again instead.
+    // So is every item up to the first one that starts on a line after the
+    // edit, a // typed on that line could comment the start of an item out.
+    // The items after that are kept with their spans moved.  When the new
+    // text does not end exactly where a kept item starts, e.g. a </loop> was
+    // deleted, the whole of source is parsed again.
+    pub fn reparse(&self, source: &str, edit: &TextEdit) -> Result<Module, ParseError> {
+        self.reparse_items(source, edit)
+            .map_or_else(|| Module::parse(source), Ok)
+    }
+
+    fn reparse_items(&self, source: &str, edit: &TextEdit) -> Option<Module> {
+        let delta = edit.text.len() as isize - edit.range.len() as isize;
+        // the edit did not happen to the text this module was parsed from
+        let old_len = source.len().checked_add_signed(-delta)?;
+        if edit.range.start > edit.range.end || edit.range.end > old_len {
+            return None;
+        }
+
+        // the item before the one the edit starts in, None to parse again
+        // from the start of source
+        let first = match self
+            .items
+            .iter()
+            .rposition(|item| item.span().start < edit.range.start)
+        {
+            Some(indx) if indx > 0 => Some(indx - 1),
+            _ => None,
+        };
+        let edit_end = edit.range.start + edit.text.len();
+        let edit_line_end = source
+            .get(edit_end..)?
+            .find('\n')
+            .map_or(source.len(), |indx| edit_end + indx);
+        let next = first.map_or(0, |indx| indx + 1);
+        let kept = self.items[next..]
+            .iter()
+            .position(|item| item.span().start as isize + delta > edit_line_end as isize)
+            .map_or(self.items.len(), |indx| next + indx);
+
+        let (start, line) = match first {
+            Some(indx) => (self.items[indx].span().start, self.items[indx].span().line),
+            None => (0, 1),
+        };
+        let end = match self.items.get(kept) {
+            Some(item) => item.span().start.checked_add_signed(delta)?,
+            None => source.len(),
+        };
+        let text = source.get(start..end)?;
+        let (rest, preamble) = match first {
+            Some(_) => (text, self.preamble.as_str()),
+            None => take_until_next_module_item(text).ok()?,
+        };
+        let lines = |text: &str| text.matches('\n').count();
+        let mut items = parse_items(rest)?;
+        let lines_before = line - 1 + lines(&text[..text.len() - rest.len()]);
+        resolve_local_spans(&mut items, source, rest, lines_before);
+
+        let line_delta = match self.items.get(kept) {
+            Some(item) => (line + lines(text)) as isize - item.span().line as isize,
+            None => 0,
+        };
+        let mut module = Module {
+            preamble: String::from(preamble),
+            items: self.items[..first.unwrap_or(0)].to_vec(),
+        };
+        module.items.extend(items);
+        for item in &self.items[kept..] {
+            let mut item = item.clone();
+            item.shift_spans(delta, line_delta);
+            module.items.push(item);
+        }
+        Some(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the same result as parsing all of the edited source, incremental says
+    // whether the items before and after the edit were re-used
+    fn check(source: &str, edit: TextEdit, incremental: bool) -> Result<Module, ParseError> {
+        let module = Module::parse(source).unwrap();
+        let edited = edit.apply(source);
+        let reparsed = module.reparse(&edited, &edit);
+        match (&reparsed, Module::parse(&edited)) {
+            (Ok(reparsed), Ok(expected)) => assert_eq!(reparsed, &expected),
+            (Err(e), Err(expected)) => assert_eq!(e, &expected),
+            (reparsed, expected) => panic!("{:?} is not {:?}", reparsed, expected),
+        }
+        let reused = module.reparse_items(&edited, &edit).is_some();
+        assert_eq!(reused, incremental, "{:?} in {:?}", edit, source);
+        reparsed
+    }
+
+    #[test]
+    fn test_reparse() {
+        let source = "intro\n[Q1] ¿uno?\n(1) sí -> Q3\n\n<loop max=2>\n[L1] a\n</loop>\n[Q2] two\n// note\n[Q3] three\n";
+        let at = |text: &str| source.find(text).unwrap();
+
+        // a word in a question
+        let edit = TextEdit::new(at("uno")..at("uno") + 3, "dos y tres");
+        let module = check(source, edit, true).unwrap();
+        assert_eq!(module.items[3].span().line, 10);
+
+        // a new line and question after the loop
+        let edit = TextEdit::new(at("[Q2]")..at("[Q2]"), "[Q9] nine\n(1) a\n");
+        let module = check(source, edit, true).unwrap();
+        assert_eq!(module.items[4].span().line, 12);
+        // text added to the end of a question
+        check(
+            source,
+            TextEdit::new(at("\n[Q3]")..at("\n[Q3]"), " more"),
+            true,
+        )
+        .unwrap();
+        check(source, TextEdit::new(at("// note")..at("[Q3]"), ""), true).unwrap();
+        // the last question
+        let edit = TextEdit::new(at("three")..source.len(), "3\n[Q4] four");
+        check(source, edit, true).unwrap();
+        // the preamble
+        check(source, TextEdit::new(0..5, "Module 1\n\n"), true).unwrap();
+        check(
+            source,
+            TextEdit::new(at("uno")..at("uno"), "\n[Q0] zero\n"),
+            true,
+        )
+        .unwrap();
+
+        // a comment that hides the start of the loop
+        let e = check(
+            source,
+            TextEdit::new(at("<loop")..at("<loop"), "// "),
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(e.message, "unmatched </loop>");
+        let e = check(source, TextEdit::new(at("</loop>")..at("[Q2]"), ""), false).unwrap_err();
+        assert_eq!(e.message, "unterminated <loop> opened at line 5");
+        check(
+            source,
+            TextEdit::new(at("[Q2]")..at("[Q2]") + 1, "</grid>"),
+            false,
+        )
+        .unwrap_err();
+
+        // an edit to the [ID of a question changes where the one before ends
+        let source = "[Q1] one\n[Q2] two\n[Q3] three\n";
+        let module = check(source, TextEdit::new(10..11, "q"), true).unwrap();
+        assert_eq!(module.items[0].span().end, 17);
+        let module = check(source, TextEdit::new(19..20, "q"), true).unwrap();
+        assert_eq!(module.items.len(), 2);
+        check(source, TextEdit::new(6..6, "</loop>"), false).unwrap_err();
+        check(source, TextEdit::new(2..2, "</loop>"), true).unwrap();
+        check(source, TextEdit::new(1..2, "x"), true).unwrap();
+    }
+
+    // a small xorshift generator, so the edits are the same on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    #[test]
+    fn test_reparse_random_edits() {
+        let sources = [
+            "intro\n[Q1] ¿uno?\n(1) sí -> Q3\n\n<loop max=2>\n[L1] a\n<grid id=G>\n(1) x\n[G_1] r\n</grid>\n</loop>\n[Q2] two\n// note\n[Q3] three\n",
+            "[Q1] one\n[Q2] two\n[Q3] three\n",
+            "// Module\n<grid id=G>\n(1) a\n[G_1] r\n</grid>\n[Q1 min=0] |__|id=X|\n#NR -> Q2\n[Q2] ñ",
+        ];
+        let pieces = [
+            "",
+            "[",
+            "]",
+            "q",
+            "Q",
+            "Q4",
+            " ",
+            "\n",
+            "//",
+            "[Q9] nine\n",
+            "<loop>",
+            "</loop>",
+            "<grid>",
+            "</grid>",
+            "(2) b",
+            "-> Q1",
+            "ñ",
+        ];
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let mut reused = 0;
+        for _ in 0..2000 {
+            let source = sources[rng.below(sources.len())];
+            let boundaries: Vec<usize> = source
+                .char_indices()
+                .map(|(indx, _)| indx)
+                .chain([source.len()])
+                .collect();
+            let start = boundaries[rng.below(boundaries.len())];
+            let end = boundaries[rng.below(boundaries.len())];
+            let (start, end) = (start.min(end), start.max(end));
+            let end = start + (end - start).min(8);
+            let end = boundaries.iter().copied().find(|&b| b >= end).unwrap();
+            let edit = TextEdit::new(start..end, pieces[rng.below(pieces.len())]);
+
+            let module = Module::parse(source).unwrap();
+            let edited = edit.apply(source);
+            match (module.reparse(&edited, &edit), Module::parse(&edited)) {
+                (Ok(reparsed), Ok(expected)) => assert_eq!(reparsed, expected, "{:?}", edit),
+                (Err(e), Err(expected)) => assert_eq!(e, expected, "{:?}", edit),
+                (reparsed, expected) => {
+                    panic!("{:?}: {:?} is not {:?}", edit, reparsed, expected)
+                }
+            }
+            reused += module.reparse_items(&edited, &edit).is_some() as usize;
+        }
+        // about half of the edits break the module, the rest should not need
+        // a whole parse
+        assert!(reused > 800, "only {} edits were incremental", reused);
+    }
+}
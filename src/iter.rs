@@ -0,0 +1,149 @@
+use crate::{
+    consumed, diagnose_on_line, offset_of, parse_question_loop_grid, parse_tag_parts,
+    parse_whitespace_or_comment, resolve_local_spans, take_block_body, take_until_next_module_item,
+    ModuleItem, ParseError,
+};
+
+// Parses the top level questions, loops and grids of a module one at a time,
+// so a caller can stop at the item it is looking for.  An item that does not
+// parse is returned as an error and parsing picks up again at the next
+// question, loop or grid, a loop or grid with an error inside is skipped
+// whole.  Only the item being parsed is held in memory, which keeps it flat
+// for dumps of many concatenated modules.
+pub fn parse_module_iter(input: &str) -> impl Iterator<Item = Result<ModuleItem, ParseError>> + '_ {
+    let (rest, _preamble) = take_until_next_module_item(input).unwrap_or(("", input));
+    ModuleItems {
+        source: input,
+        rest,
+        counted: 0,
+        lines: 0,
+    }
+}
+
+struct ModuleItems<'a> {
+    source: &'a str,
+    rest: &'a str,
+    // the newlines before counted, so lines are not recounted for each item
+    counted: usize,
+    lines: usize,
+}
+
+impl ModuleItems<'_> {
+    // resolves the spans of an item parsed from text, only looking at the
+    // lines it is on.
+    fn resolve_spans(&mut self, item: &mut ModuleItem, text: &str) {
+        let offset = offset_of(self.source, text);
+        let line_start = self.source[..offset].rfind('\n').map_or(0, |indx| indx + 1);
+        self.lines +=
+            memchr::memchr_iter(b'\n', &self.source.as_bytes()[self.counted..line_start]).count();
+        self.counted = line_start;
+        resolve_local_spans(std::slice::from_mut(item), self.source, text, self.lines);
+    }
+
+    // the line of an offset at or after counted
+    fn line_of(&self, offset: usize) -> usize {
+        let bytes = &self.source.as_bytes()[self.counted..offset];
+        self.lines + memchr::memchr_iter(b'\n', bytes).count() + 1
+    }
+}
+
+impl Iterator for ModuleItems<'_> {
+    type Item = Result<ModuleItem, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (rest, _) = parse_whitespace_or_comment(self.rest).unwrap_or((self.rest, ""));
+        self.rest = rest;
+        if rest.is_empty() {
+            return None;
+        }
+        if let Ok((next, mut item)) = parse_question_loop_grid(rest) {
+            self.rest = next;
+            self.resolve_spans(&mut item, consumed(rest, next));
+            return Some(Ok(item));
+        }
+
+        let error = diagnose_on_line(self.source, rest, &|offset| self.line_of(offset));
+        let block_end = parse_tag_parts(rest)
+            .and_then(|(body, (name, _))| take_block_body(body, name))
+            .map(|(next, _)| next);
+        self.rest = match block_end {
+            Ok(next) => next,
+            Err(_) => {
+                let skip = rest.chars().next().map_or(0, char::len_utf8);
+                take_until_next_module_item(&rest[skip..]).map_or("", |(next, _)| next)
+            }
+        };
+        Some(Err(error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Module;
+
+    #[test]
+    fn test_parse_module_iter() {
+        let markdown = "intro\n[Q1] ¿uno?\n(1) sí\n\n<loop max=2>\n  [L1] a\n</loop>\n// done\n<grid id=G>\n[G1] r\n</grid>\n[Q2] two";
+        let items: Vec<_> = parse_module_iter(markdown).map(Result::unwrap).collect();
+        let module = Module::parse(markdown).unwrap();
+        assert_eq!(items, module.items);
+
+        // stops at the first match without looking at the rest
+        let found = parse_module_iter("[Q1] a\n[Q2] b\n[Q3 <loop> never parsed")
+            .filter_map(Result::ok)
+            .find(|item| matches!(item, ModuleItem::Question(q) if q.id() == "Q2"));
+        assert_eq!(found.unwrap().span().line, 2);
+
+        let markdown = "[Q1] a\n</loop>\n[Q2] b\n<loop>\n[L1] c\n</grid>\n</loop>\n[Q3] d";
+        let results: Vec<_> = parse_module_iter(markdown)
+            .map(|result| match result {
+                Ok(item) => item.span().line.to_string(),
+                Err(e) => e.message,
+            })
+            .collect();
+        assert_eq!(
+            results,
+            vec!["1", "unmatched </loop>", "3", "unmatched </grid>", "8"]
+        );
+        // counted from the lines seen so far, the same as for a whole parse
+        let errors: Vec<_> = parse_module_iter(markdown)
+            .filter_map(Result::err)
+            .collect();
+        assert_eq!(errors, Module::parse_recovering(markdown).1);
+        assert_eq!((errors[1].line, errors[1].column), (6, 1));
+        let e = parse_module_iter("[Q1] a\n\n<loop>\n[L1] b")
+            .nth(1)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(e.message, "unterminated <loop> opened at line 3");
+    }
+
+    #[test]
+    fn test_same_as_module_parse() {
+        let inputs = [
+            "<loop max=2>\n//[Q1]  </loop>\n",
+            "<loop max=2>\n[L1] a // </loop>\n</loop>\n[Q2] b",
+            "<loop max=2>\n// <loop>\n[L1] a\n</loop>",
+            "[Q1] one\n<grid id=G>\n(1) a\n// </grid>\n[G_1] r\n</grid>",
+            "// c\n<loop max=2>\n//</loop>\n(1) yes -> Q2\n<loop max=2 counter=Q1>",
+            "[Q1] a\n</loop>\n[Q2] b",
+        ];
+        for input in inputs {
+            let results: Vec<_> = parse_module_iter(input).collect();
+            let borrowed = crate::borrowed::Module::parse(input).map(|m| m.into_owned());
+            match Module::parse(input) {
+                Ok(module) => {
+                    let items: Vec<_> = results.into_iter().map(Result::unwrap).collect();
+                    assert_eq!(items, module.items, "{:?}", input);
+                    assert_eq!(borrowed, Ok(module), "{:?}", input);
+                }
+                Err(e) => {
+                    let first = results.into_iter().find_map(Result::err);
+                    assert_eq!(first.as_ref(), Some(&e), "{:?}", input);
+                    assert_eq!(borrowed, Err(e), "{:?}", input);
+                }
+            }
+        }
+    }
+}
@@ -1,73 +1,514 @@
 #[allow(unused_imports)]
 use nom::branch::alt;
 use nom::bytes::complete::tag;
+use nom::bytes::complete::take_till1;
 use nom::bytes::complete::take_until;
 use nom::bytes::complete::take_while;
+use nom::character::complete::multispace0;
 use nom::character::complete::multispace1;
 use nom::character::complete::space0;
 use nom::combinator::opt;
-use nom::multi::{many0, many1};
-use nom::sequence::delimited;
+use nom::multi::{fold_many0, many0, many0_count};
 use nom::sequence::terminated;
 use nom::sequence::tuple;
 use nom::IResult;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
 
-#[derive(Debug)]
+pub mod borrowed;
+mod cst;
+mod error;
+mod eval;
+mod expr;
+mod format;
+mod incremental;
+mod iter;
+mod lint;
+mod navigation;
+mod render;
+mod runner;
+mod scan;
+#[cfg(feature = "serde")]
+mod schema;
+mod source;
+mod span;
+mod symbols;
+pub use cst::{SyntaxKind, SyntaxNode, SyntaxTree};
+pub use error::ParseError;
+pub use eval::{evaluate, ResponseStore, Value};
+pub use expr::{parse_expr, Expr, ExprError};
+pub use format::format_module;
+pub use incremental::TextEdit;
+pub use iter::parse_module_iter;
+pub use lint::{lint, Diagnostic, LintConfig, Rule, Severity};
+pub use navigation::{Edge, EdgeKind, NavigationGraph};
+pub use render::render_html;
+pub use runner::SurveyRunner;
+#[cfg(feature = "serde")]
+pub use schema::SCHEMA_VERSION;
+pub use source::{LoadError, LoadedModule, ModuleLoader, ModuleSource, DEFAULT_BASE_URL};
+pub use span::Span;
+use span::{line_column, offset_of, LineIndex};
+pub use symbols::{Collision, Symbol, SymbolKind, SymbolTable};
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Module {
     pub preamble: String,
     pub items: Vec<ModuleItem>,
 }
 
-#[derive(Debug, PartialEq)]
+// The text between the [ and ] of a question, e.g.
+// [Q2? displayif=equals(Q1,1) min=0]
+#[derive(Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QuestionHeader {
+    pub id: String,
+    // a trailing '?' on the id
+    pub soft_required: bool,
+    // a trailing '!' on the id
+    pub hard_required: bool,
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl QuestionHeader {
+    pub fn new(header: &str) -> Self {
+        // parse_header cannot fail, anything it does not understand
+        // is left out of the attributes.
+        match parse_header(header) {
+            Ok((_, header)) => header,
+            Err(_) => QuestionHeader {
+                id: String::from(header.trim()),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes.get(key).map(|v| v.as_str())
+    }
+
+    pub fn displayif(&self) -> Option<Result<Expr, ExprError>> {
+        self.attribute("displayif").map(parse_expr)
+    }
+}
+
+// writes the header back out as it would appear between the [ and ].
+impl fmt::Display for QuestionHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.id)?;
+        if self.soft_required {
+            write!(f, "?")?;
+        }
+        if self.hard_required {
+            write!(f, "!")?;
+        }
+        write_attributes(f, &self.attributes)
+    }
+}
+
+fn write_attributes(f: &mut fmt::Formatter, attributes: &BTreeMap<String, String>) -> fmt::Result {
+    for (key, value) in attributes {
+        write!(f, " {}", key)?;
+        if !value.is_empty() {
+            write!(f, "={}", quote_attribute_value(value))?;
+        }
+    }
+    Ok(())
+}
+
+// values are only quoted when they would not read back the same without quotes.
+fn quote_attribute_value(value: &str) -> String {
+    match take_attribute_value(value) {
+        Ok(("", unquoted)) if unquoted == value && !value.starts_with(['"', '\'']) => {
+            String::from(value)
+        }
+        _ if value.contains('"') => format!("'{}'", value),
+        _ => format!("\"{}\"", value),
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "lowercase"))]
+pub enum InputField {
+    // |__|
+    Text,
+    // |__|__|, or |__| with a min or max
+    Number {
+        min: Option<String>,
+        max: Option<String>,
+    },
+    // |date|
+    Date,
+    // |month|
+    Month,
+    // |email|
+    Email,
+    // |tel|
+    Tel,
+    // |___|
+    TextBox,
+}
+
+// An input marker found in a question or response, e.g. |__|id=age min=0|
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Input {
+    pub field: InputField,
+    pub id: Option<String>,
+    pub attributes: BTreeMap<String, String>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ResponseKind {
+    // (value) label
+    Radio,
+    // [value] label
+    Checkbox,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Response {
+    pub kind: ResponseKind,
+    pub value: String,
+    pub label: String,
+    pub attributes: BTreeMap<String, String>,
+    // input markers in the label, e.g. (77) Other: |__|
+    pub inputs: Vec<Input>,
+    // (1) Yes -> Q5
+    pub goto: Option<String>,
+}
+
+impl Response {
+    fn new(kind: ResponseKind, value: &str, label: &str) -> Self {
+        let (label, goto) = split_goto(label);
+        Response {
+            kind,
+            value: String::from(value.trim()),
+            label: String::from(label.trim()),
+            attributes: BTreeMap::new(),
+            inputs: find_inputs(label),
+            goto: goto.map(String::from),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Question {
-    pub header: String,
+    pub header: QuestionHeader,
     pub markdown: String,
+    // the markdown before the first response option
+    pub text: String,
+    pub responses: Vec<Response>,
+    // input markers in the prompt text, inputs in a response
+    // label belong to the response.
+    pub inputs: Vec<Input>,
+    // a "-> ID" at the end of the prompt text
+    pub goto: Option<String>,
+    // a "#NR -> ID" line, where to go if the question is not answered
+    pub no_response_goto: Option<String>,
+    // from the [ to the end of the markdown
+    pub span: Span,
+    // between the [ and ]
+    pub header_span: Span,
+    pub markdown_span: Span,
 }
 impl Question {
     fn new(header: &str, markdown: &str) -> Self {
+        let markdown = markdown.trim();
+        let (text, responses) = split_responses(markdown);
+
+        // most questions have no #NR line, which saves looking for one
+        let no_response_goto = markdown
+            .contains("#NR")
+            .then(|| {
+                markdown
+                    .lines()
+                    .find_map(|line| parse_no_response(line).ok())
+            })
+            .flatten()
+            .map(|(_, goto)| String::from(goto));
+        let prompt: Cow<str> = match text.contains("#NR") {
+            true => text
+                .split_inclusive('\n')
+                .filter(|line| parse_no_response(line).is_err())
+                .collect(),
+            false => Cow::Borrowed(text),
+        };
+        let (prompt, goto) = split_goto(&prompt);
+
         Question {
-            header: String::from(header.trim()),
-            markdown: String::from(markdown.trim()),
+            header: QuestionHeader::new(header),
+            markdown: String::from(markdown),
+            text: String::from(prompt.trim()),
+            responses,
+            inputs: find_inputs(prompt),
+            goto: goto.map(String::from),
+            no_response_goto,
+            span: Span::default(),
+            header_span: Span::default(),
+            markdown_span: Span::default(),
         }
     }
 
+    pub fn id(&self) -> &str {
+        &self.header.id
+    }
+
+    #[allow(clippy::needless_return)]
     pub fn render_markdown(&self) -> &str {
         return &self.markdown;
     }
 }
 
-#[derive(Debug, PartialEq)]
+// <grid id=G1>
+// shared prompt
+// (1) column 1
+// (2) column 2
+// [G1_1] row 1
+// [G1_2] row 2
+// </grid>
+// the column options may also follow the last row.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Grid {
     tag: Tag,
     markdown: String,
+    pub id: Option<String>,
+    pub displayif: Option<String>,
+    pub prompt: String,
+    pub rows: Vec<Question>,
+    pub columns: Vec<Response>,
+    pub kind: ResponseKind,
+    // from <grid to the end of </grid>
+    pub span: Span,
+    pub body_span: Span,
+    pub close_span: Span,
 }
 
 impl Grid {
     fn new(tag: Tag, markdown: &str) -> Self {
+        let markdown = markdown.trim();
+        let attributes = tag.attributes();
+        let (prompt, rows, columns) = parse_grid_body(markdown);
+        let kind = if columns.iter().any(|c| c.kind == ResponseKind::Checkbox) {
+            ResponseKind::Checkbox
+        } else {
+            ResponseKind::Radio
+        };
         Grid {
             tag,
-            markdown: String::from(markdown.trim()),
+            markdown: String::from(markdown),
+            id: attributes.get("id").cloned(),
+            displayif: attributes.get("displayif").cloned(),
+            prompt,
+            rows,
+            columns,
+            kind,
+            span: Span::default(),
+            body_span: Span::default(),
+            close_span: Span::default(),
         }
     }
+
+    pub fn tag(&self) -> &Tag {
+        &self.tag
+    }
+
+    pub fn markdown(&self) -> &str {
+        &self.markdown
+    }
+
+    // each row as a stand alone question whose responses are the columns.
+    pub fn row_questions(&self) -> Vec<Question> {
+        self.rows
+            .iter()
+            .map(|row| {
+                let mut question = row.clone();
+                question.responses = self.columns.clone();
+                question
+            })
+            .collect()
+    }
+}
+
+fn parse_grid_body(markdown: &str) -> (String, Vec<Question>, Vec<Response>) {
+    let (rows_input, preamble) = take_until_next_module_item(markdown).unwrap_or(("", markdown));
+    let (prompt, mut columns) = split_responses(preamble);
+    let rows: Vec<Question> = many0(parse_question_loop_grid)(rows_input)
+        .map(|(_, items)| items)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|item| match item {
+            ModuleItem::Question(q) => Some(q),
+            _ => None,
+        })
+        .map(|mut row| {
+            for response in row.responses.drain(..) {
+                if !columns.iter().any(|c| c.value == response.value) {
+                    columns.push(response);
+                }
+            }
+            row
+        })
+        .collect();
+    (String::from(prompt.trim()), rows, columns)
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Loop {
     tag: Tag,
     markdown: String,
     questions: Vec<ModuleItem>,
+    pub id: Option<String>,
+    // the most iterations the loop can have
+    pub max: Option<usize>,
+    pub first_question: Option<String>,
+    pub displayif: Option<String>,
+    // the question whose answer is the number of iterations
+    pub counter: Option<String>,
+    // from <loop to the end of </loop>
+    pub span: Span,
+    pub body_span: Span,
+    pub close_span: Span,
 }
 impl Loop {
     fn new(tag: Tag, markdown: &str, questions: Vec<ModuleItem>) -> Self {
+        let attributes = tag.attributes();
         Loop {
-            tag,
             markdown: String::from(markdown.trim()),
             questions,
+            id: attributes.get("id").cloned(),
+            max: attributes.get("max").and_then(|max| max.parse().ok()),
+            first_question: attributes.get("firstquestion").cloned(),
+            displayif: attributes.get("displayif").cloned(),
+            counter: attributes.get("counter").cloned(),
+            tag,
+            span: Span::default(),
+            body_span: Span::default(),
+            close_span: Span::default(),
         }
     }
+
+    pub fn tag(&self) -> &Tag {
+        &self.tag
+    }
+
+    pub fn markdown(&self) -> &str {
+        &self.markdown
+    }
+
+    pub fn questions(&self) -> &[ModuleItem] {
+        &self.questions
+    }
+
+    // The ids of every question in the loop body, including nested loops
+    // and grid rows.
+    fn inner_ids(&self) -> Vec<String> {
+        let mut ids = Vec::new();
+        for item in &self.questions {
+            match item {
+                ModuleItem::Question(q) => ids.push(q.header.id.clone()),
+                ModuleItem::Loop(l) => ids.extend(l.inner_ids()),
+                ModuleItem::Grid(g) => ids.extend(g.rows.iter().map(|row| row.header.id.clone())),
+            }
+        }
+        ids
+    }
+
+    // Unrolls the loop into n copies of its body (at most max).  In the
+    // i-th copy the ids of questions, grid rows, inputs and nested loops and
+    // grids get a _i suffix, #loop is replaced by i, and skip arrows to
+    // questions inside the loop point at the same iteration.  Each copy
+    // keeps the spans of the original it came from.
+    pub fn expand(&self, n: usize) -> Vec<ModuleItem> {
+        let n = self.max.map_or(n, |max| n.min(max));
+        let inner_ids = self.inner_ids();
+        let mut items = Vec::new();
+        for iteration in 1..=n {
+            for item in &self.questions {
+                let mut expanded = expand_item(item, iteration, &inner_ids);
+                expanded.copy_spans(item);
+                items.push(expanded);
+            }
+        }
+        items
+    }
 }
 
-#[derive(Debug, PartialEq)]
+fn expand_item(item: &ModuleItem, iteration: usize, inner_ids: &[String]) -> ModuleItem {
+    let substitute = |text: &str| text.replace("#loop", &iteration.to_string());
+    let suffix = |id: &String| format!("{}_{}", substitute(id), iteration);
+    match item {
+        ModuleItem::Question(q) => ModuleItem::Question(expand_question(q, iteration, inner_ids)),
+        ModuleItem::Loop(l) => {
+            let tag = Tag::new(&l.tag.name, &substitute(&l.tag.params));
+            let questions = l
+                .questions
+                .iter()
+                .map(|item| expand_item(item, iteration, inner_ids))
+                .collect();
+            let mut expanded = Loop::new(tag, &substitute(&l.markdown), questions);
+            expanded.id = l.id.as_ref().map(suffix);
+            ModuleItem::Loop(expanded)
+        }
+        ModuleItem::Grid(g) => {
+            let tag = Tag::new(&g.tag.name, &substitute(&g.tag.params));
+            let mut expanded = Grid::new(tag, &substitute(&g.markdown));
+            expanded.id = g.id.as_ref().map(suffix);
+            expanded.rows = g
+                .rows
+                .iter()
+                .map(|row| expand_question(row, iteration, inner_ids))
+                .collect();
+            ModuleItem::Grid(expanded)
+        }
+    }
+}
+
+fn expand_question(q: &Question, iteration: usize, inner_ids: &[String]) -> Question {
+    let substitute = |text: &str| text.replace("#loop", &iteration.to_string());
+    let suffix = |id: &String| format!("{}_{}", substitute(id), iteration);
+    let rename = |id: &String| {
+        if inner_ids.contains(id) {
+            format!("{}_{}", id, iteration)
+        } else {
+            id.clone()
+        }
+    };
+    let mut header = q.header.clone();
+    header.id = suffix(&header.id);
+    for value in header.attributes.values_mut() {
+        *value = substitute(value);
+    }
+    let mut question = Question::new(&header.to_string(), &substitute(&q.markdown));
+    question.goto = question.goto.as_ref().map(rename);
+    question.no_response_goto = question.no_response_goto.as_ref().map(rename);
+    // the markdown keeps the ids of the unexpanded question, so the ids of
+    // inputs are taken from q, which in a nested loop already has a suffix
+    let suffix_inputs = |inputs: &mut [Input], originals: &[Input]| {
+        for (input, original) in inputs.iter_mut().zip(originals) {
+            input.id = original.id.as_ref().map(suffix);
+        }
+    };
+    for (response, original) in question.responses.iter_mut().zip(&q.responses) {
+        response.goto = response.goto.as_ref().map(rename);
+        suffix_inputs(&mut response.inputs, &original.inputs);
+    }
+    suffix_inputs(&mut question.inputs, &q.inputs);
+    question
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "lowercase"))]
 pub enum ModuleItem {
     Question(Question),
     Loop(Loop),
@@ -75,21 +516,155 @@ pub enum ModuleItem {
 }
 
 impl ModuleItem {
+    pub fn span(&self) -> Span {
+        match self {
+            ModuleItem::Question(q) => q.span,
+            ModuleItem::Loop(l) => l.span,
+            ModuleItem::Grid(g) => g.span,
+        }
+    }
+
     fn new_question(header: &str, markdown: &str) -> Self {
         ModuleItem::Question(Question::new(header, markdown))
     }
+    // a question parsed from whole, which is the [header] and the markdown
+    fn located_question(whole: &str, header: &str, markdown: &str) -> Self {
+        let mut item = ModuleItem::new_question(header, markdown);
+        if let ModuleItem::Question(question) = &mut item {
+            question.span = Span::locate(whole.trim_end());
+            question.header_span = Span::locate(header);
+            question.markdown_span = Span::locate(markdown.trim());
+        }
+        item
+    }
     fn new_loop(tag: Tag, markdown: &str, items: Vec<ModuleItem>) -> Self {
         ModuleItem::Loop(Loop::new(tag, markdown, items))
     }
     fn new_grid(tag: Tag, markdown: &str) -> Self {
         ModuleItem::Grid(Grid::new(tag, markdown))
     }
+
+    // sets the spans of a loop or grid that was parsed from whole, which is
+    // the opening tag, the body and the closing tag.
+    fn locate_block(&mut self, whole: &str, body: &str, close: &str) {
+        let (span, body_span, close_span) = match self {
+            ModuleItem::Question(_) => return,
+            ModuleItem::Loop(l) => (&mut l.span, &mut l.body_span, &mut l.close_span),
+            ModuleItem::Grid(g) => (&mut g.span, &mut g.body_span, &mut g.close_span),
+        };
+        *span = Span::locate(whole);
+        *body_span = Span::locate(body.trim());
+        *close_span = Span::locate(close);
+    }
+
+    // every span in the item, including those of the items in a loop and
+    // the rows of a grid
+    fn spans_mut(&mut self) -> Vec<&mut Span> {
+        let mut spans = Vec::new();
+        self.collect_spans(&mut spans);
+        spans
+    }
+
+    fn collect_spans<'a>(&'a mut self, spans: &mut Vec<&'a mut Span>) {
+        match self {
+            ModuleItem::Question(q) => spans.extend(q.spans_mut()),
+            ModuleItem::Loop(l) => {
+                spans.extend([&mut l.span, &mut l.body_span, &mut l.close_span]);
+                spans.extend([&mut l.tag.span, &mut l.tag.params_span]);
+                for item in l.questions.iter_mut() {
+                    item.collect_spans(spans);
+                }
+            }
+            ModuleItem::Grid(g) => {
+                spans.extend([&mut g.span, &mut g.body_span, &mut g.close_span]);
+                spans.extend([&mut g.tag.span, &mut g.tag.params_span]);
+                for row in g.rows.iter_mut() {
+                    spans.extend(row.spans_mut());
+                }
+            }
+        }
+    }
+
+    fn resolve_spans(&mut self, source: &str, lines: &LineIndex) {
+        for span in self.spans_mut() {
+            span.resolve(source, lines);
+        }
+    }
+
+    fn shift_spans(&mut self, offset: isize, lines: isize) {
+        for span in self.spans_mut() {
+            span.shift(offset, lines);
+        }
+    }
+
+    // gives a copy made from original (e.g. by Loop::expand) its spans.
+    fn copy_spans(&mut self, original: &ModuleItem) {
+        match (self, original) {
+            (ModuleItem::Question(q), ModuleItem::Question(o)) => q.copy_spans(o),
+            (ModuleItem::Loop(l), ModuleItem::Loop(o)) => {
+                (l.span, l.body_span, l.close_span) = (o.span, o.body_span, o.close_span);
+                (l.tag.span, l.tag.params_span) = (o.tag.span, o.tag.params_span);
+                for (item, original) in l.questions.iter_mut().zip(&o.questions) {
+                    item.copy_spans(original);
+                }
+            }
+            (ModuleItem::Grid(g), ModuleItem::Grid(o)) => {
+                (g.span, g.body_span, g.close_span) = (o.span, o.body_span, o.close_span);
+                (g.tag.span, g.tag.params_span) = (o.tag.span, o.tag.params_span);
+                for (row, original) in g.rows.iter_mut().zip(&o.rows) {
+                    row.copy_spans(original);
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
-struct Tag {
-    name: String,
-    params: String,
+impl Question {
+    fn spans_mut(&mut self) -> [&mut Span; 3] {
+        [
+            &mut self.span,
+            &mut self.header_span,
+            &mut self.markdown_span,
+        ]
+    }
+
+    fn copy_spans(&mut self, original: &Question) {
+        self.span = original.span;
+        self.header_span = original.header_span;
+        self.markdown_span = original.markdown_span;
+    }
+}
+
+fn resolve_spans(items: &mut [ModuleItem], source: &str) {
+    let lines = LineIndex::new(source);
+    for item in items.iter_mut() {
+        item.resolve_spans(source, &lines);
+    }
+}
+
+// Resolves the spans of items parsed from text, a slice of source that
+// starts after lines_before whole lines, only indexing the lines of text.
+fn resolve_local_spans(items: &mut [ModuleItem], source: &str, text: &str, lines_before: usize) {
+    let offset = offset_of(source, text);
+    let line_start = source[..offset].rfind('\n').map_or(0, |indx| indx + 1);
+    let local = &source[line_start..offset + text.len()];
+    let lines = LineIndex::new(local);
+    for item in items.iter_mut() {
+        item.resolve_spans(local, &lines);
+        item.shift_spans(line_start as isize, lines_before as isize);
+    }
+}
+
+// <loop max=5> has the name loop and the params max=5
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tag {
+    pub name: String,
+    pub params: String,
+    // from the < to the >
+    pub span: Span,
+    pub params_span: Span,
 }
 
 impl Tag {
@@ -97,62 +672,300 @@ impl Tag {
         Tag {
             name: String::from(name.trim()),
             params: String::from(params.trim()),
+            span: Span::default(),
+            params_span: Span::default(),
         }
     }
+
+    // a tag parsed from whole, which runs from the < to the >
+    fn located(whole: &str, name: &str, params: &str) -> Self {
+        let mut tag = Tag::new(name, params);
+        tag.span = Span::locate(whole);
+        tag.params_span = Span::locate(params.trim());
+        tag
+    }
+
+    fn attributes(&self) -> BTreeMap<String, String> {
+        parse_attributes(&self.params)
+            .map(|(_, attributes)| attributes)
+            .unwrap_or_default()
+    }
 }
 
 fn take_until_next_module_item(input: &str) -> IResult<&str, &str> {
+    let end = scan::next_item(input).unwrap_or(input.len());
+    Ok((&input[end..], &input[..end]))
+}
+
+// Attribute values may be quoted ("a b" or 'a b'); unquoted values run until
+// whitespace or a comma that is not nested inside (), [] or {} so that
+// displayif=equals(Q1, 1) stays in one piece.
+fn take_attribute_value(input: &str) -> IResult<&str, &str> {
     let mut chars = input.char_indices();
-    let mut char2 = input.chars();
-    let _ = char2.next();
-
-    while let Some((current_index, current_char)) = chars.next() {
-        let optional_next_char = char2.next();
-        match (current_char, optional_next_char) {
-            // there is a comment scan until \n or EOS...
-            ('/', Some('/')) => {
-                while let Some((_, in_comment_char)) = chars.next() {
-                    let _ = char2.next();
-                    if in_comment_char == '\n' {
-                        break;
-                    }
+    match chars.next() {
+        Some((_, quote)) if quote == '"' || quote == '\'' => {
+            for (indx, c) in chars {
+                if c == quote {
+                    return Ok((&input[indx + 1..], &input[1..indx]));
                 }
             }
-            // hit new question?
-            ('[', Some(next_char)) if next_char.is_uppercase() => {
-                return Ok((&input[current_index..], &input[0..current_index]));
-            }
-            // hit grid/loop?
-            ('<', Some(_)) => {
-                // safely calculate start and end to make sure that
-                // we dont hit any multi-byte characters.  If we hit a multibyte
-                // character, then it is not the end of the module item.
-                let start = (current_index + 1).min(input.len());
-                let end = (current_index + 5).min(input.len());
-                if input.is_char_boundary(end) && end > start {
-                    let tag = &input[start..end];
-                    if tag == "loop" || tag == "grid" {
-                        //println!("--- found tag {}", tag);
-                        return Ok((&input[current_index..], &input[0..current_index]));
+            // unterminated quote, take the rest of the header.
+            Ok(("", &input[1..]))
+        }
+        _ => {
+            let mut depth = 0;
+            let mut in_quote: Option<char> = None;
+            for (indx, c) in input.char_indices() {
+                match (in_quote, c) {
+                    (Some(q), c) if c == q => in_quote = None,
+                    (Some(_), _) => {}
+                    (None, '"') | (None, '\'') => in_quote = Some(c),
+                    (None, '(') | (None, '[') | (None, '{') => depth += 1,
+                    (None, ')') | (None, ']') | (None, '}') if depth > 0 => depth -= 1,
+                    (None, c) if depth == 0 && (c.is_whitespace() || c == ',') => {
+                        return Ok((&input[indx..], &input[0..indx]));
                     }
+                    (None, _) => {}
                 }
             }
-            // keep going
-            (_, _) => {}
+            Ok(("", input))
         }
     }
-    Ok(("", input))
 }
 
-fn parse_question(input: &str) -> IResult<&str, ModuleItem> {
-    let (input, header) = delimited(tag("["), take_until("]"), tag("]"))(input)?;
-    let (input, markdown) = take_until_next_module_item(input)?;
+fn attribute_separator(input: &str) -> IResult<&str, &str> {
+    take_while(|c: char| c.is_whitespace() || c == ',')(input)
+}
 
-    let item = ModuleItem::new_question(header, markdown);
-    Ok((input, item))
+fn parse_attribute(input: &str) -> IResult<&str, (&str, &str)> {
+    let (input, _) = attribute_separator(input)?;
+    let (input, key) = take_till1(|c: char| c.is_whitespace() || c == ',' || c == '=')(input)?;
+    let (input, value) = opt(tuple((space0, tag("="), space0, take_attribute_value)))(input)?;
+    let value = value.map(|(_, _, _, value)| value).unwrap_or("");
+    Ok((input, (key, value)))
 }
 
-fn parse_tag(input: &str) -> IResult<&str, Tag> {
+fn parse_attributes(input: &str) -> IResult<&str, BTreeMap<String, String>> {
+    let (input, attributes) =
+        fold_many0(parse_attribute, BTreeMap::new, |mut map, (key, value)| {
+            map.insert(String::from(key), String::from(value));
+            map
+        })(input)?;
+    let (input, _) = attribute_separator(input)?;
+    Ok((input, attributes))
+}
+
+fn parse_header(input: &str) -> IResult<&str, QuestionHeader> {
+    let (input, _) = space0(input)?;
+    let (input, id) = take_till1(|c: char| c.is_whitespace() || ",?!=".contains(c))(input)?;
+    let (input, markers) = take_while(|c| c == '?' || c == '!')(input)?;
+    let (input, attributes) = parse_attributes(input)?;
+    let header = QuestionHeader {
+        id: String::from(id),
+        soft_required: markers.contains('?'),
+        hard_required: markers.contains('!'),
+        attributes,
+    };
+    Ok((input, header))
+}
+
+// the attribute section of an input marker must be a non-empty list of
+// key=value pairs, otherwise |__| and |__| would read " and " as attributes.
+fn parse_input_attributes(input: &str) -> IResult<&str, BTreeMap<String, String>> {
+    let (rest, contents) = terminated(take_till1(|c: char| c == '|'), tag("|"))(input)?;
+    let (remaining, attributes) = parse_attributes(contents)?;
+    if contents.starts_with(char::is_whitespace)
+        || !remaining.is_empty()
+        || attributes.is_empty()
+        || attributes.values().any(|value| value.is_empty())
+    {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+    Ok((rest, attributes))
+}
+
+fn parse_input(input: &str) -> IResult<&str, Input> {
+    let (input, (_, kind, _)) = tuple((
+        tag("|"),
+        alt((
+            tag("__|__"),
+            tag("___"),
+            tag("__"),
+            tag("date"),
+            tag("month"),
+            tag("email"),
+            tag("tel"),
+        )),
+        tag("|"),
+    ))(input)?;
+    let (input, attributes) = opt(parse_input_attributes)(input)?;
+    let attributes = attributes.unwrap_or_default();
+
+    let min = attributes.get("min").cloned();
+    let max = attributes.get("max").cloned();
+    let field = match kind {
+        "__|__" => InputField::Number { min, max },
+        "__" if min.is_some() || max.is_some() => InputField::Number { min, max },
+        "__" => InputField::Text,
+        "___" => InputField::TextBox,
+        "date" => InputField::Date,
+        "month" => InputField::Month,
+        "email" => InputField::Email,
+        "tel" => InputField::Tel,
+        _ => unreachable!(),
+    };
+    let input_field = Input {
+        field,
+        id: attributes.get("id").cloned(),
+        attributes,
+    };
+    Ok((input, input_field))
+}
+
+// text split at its input markers, each piece of text is followed by the
+// input after it (None for the last piece).
+fn split_inputs(text: &str) -> Vec<(&str, Option<Input>)> {
+    let mut pieces = Vec::new();
+    let mut piece = text;
+    let mut rest = text;
+    while let Some(indx) = rest.find('|') {
+        match parse_input(&rest[indx..]) {
+            Ok((remaining, input)) => {
+                let end = piece.len() - (rest.len() - indx);
+                pieces.push((&piece[..end], Some(input)));
+                piece = remaining;
+                rest = remaining;
+            }
+            Err(_) => rest = &rest[indx + 1..],
+        }
+    }
+    pieces.push((piece, None));
+    pieces
+}
+
+fn find_inputs(text: &str) -> Vec<Input> {
+    let mut inputs = Vec::new();
+    let mut rest = text;
+    while let Some(indx) = rest.find('|') {
+        match parse_input(&rest[indx..]) {
+            Ok((remaining, input)) => {
+                inputs.push(input);
+                rest = remaining;
+            }
+            Err(_) => rest = &rest[indx + 1..],
+        }
+    }
+    inputs
+}
+
+fn parse_goto(input: &str) -> IResult<&str, &str> {
+    let (input, (_, _, _, target, _)) = tuple((
+        space0,
+        tag("->"),
+        space0,
+        take_till1(|c: char| c.is_whitespace()),
+        multispace0,
+    ))(input)?;
+    Ok((input, target))
+}
+
+// splits a trailing "-> ID" off of a label or prompt.
+fn split_goto(text: &str) -> (&str, Option<&str>) {
+    if let Some(indx) = text.rfind("->") {
+        if let Ok(("", target)) = parse_goto(&text[indx..]) {
+            return (&text[0..indx], Some(target));
+        }
+    }
+    (text, None)
+}
+
+// #NR -> Q9
+fn parse_no_response(input: &str) -> IResult<&str, &str> {
+    let (input, (_, _, target)) = tuple((space0, tag("#NR"), parse_goto))(input)?;
+    Ok((input, target))
+}
+
+// takes "(...)" or "[...]" returning the opening bracket and the contents,
+// brackets and quotes inside the contents are balanced.
+fn take_bracketed(input: &str) -> IResult<&str, (char, &str)> {
+    let (input, open) = alt((tag("("), tag("[")))(input)?;
+    let close = if open == "(" { ')' } else { ']' };
+    let mut depth = 0;
+    let mut in_quote: Option<char> = None;
+    for (indx, c) in input.char_indices() {
+        match (in_quote, c) {
+            (Some(q), c) if c == q => in_quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => in_quote = Some(c),
+            (None, c) if c == close && depth == 0 => {
+                let open = if open == "(" { '(' } else { '[' };
+                return Ok((&input[indx + 1..], (open, &input[0..indx])));
+            }
+            (None, '(') | (None, '[') | (None, '{') => depth += 1,
+            (None, ')') | (None, ']') | (None, '}') if depth > 0 => depth -= 1,
+            (None, _) => {}
+        }
+    }
+    Err(nom::Err::Error(nom::error::Error::new(
+        input,
+        nom::error::ErrorKind::TakeUntil,
+    )))
+}
+
+// a response is a line starting with (value) or [value], optionally followed
+// by key=value attributes inside the brackets.  Anything else in brackets,
+// such as "(Select all that apply)", is part of the question text.
+fn parse_response(input: &str) -> IResult<&str, Response> {
+    let (input, _) = space0(input)?;
+    let (input, (open, contents)) = take_bracketed(input)?;
+    let (rest, (_, value)) =
+        tuple((space0, take_till1(|c: char| c.is_whitespace() || c == ',')))(contents)?;
+    let (rest, attributes) = parse_attributes(rest)?;
+    if !rest.is_empty() || attributes.values().any(String::is_empty) {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            contents,
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+    let (input, label) = take_while(|c| c != '\n')(input)?;
+
+    let kind = if open == '(' {
+        ResponseKind::Radio
+    } else {
+        ResponseKind::Checkbox
+    };
+    let mut response = Response::new(kind, value, label);
+    response.attributes = attributes;
+    Ok((input, response))
+}
+
+// splits question markdown into the prompt text and the response options.
+fn split_responses(markdown: &str) -> (&str, Vec<Response>) {
+    let mut text_end = markdown.len();
+    let mut responses = Vec::new();
+    let mut line_start = 0;
+    for line in markdown.split_inclusive('\n') {
+        if let Ok((_, response)) = parse_response(line) {
+            if responses.is_empty() {
+                text_end = line_start;
+            }
+            responses.push(response);
+        }
+        line_start += line.len();
+    }
+    (&markdown[0..text_end], responses)
+}
+
+// the part of start that was consumed to get to rest
+fn consumed<'a>(start: &'a str, rest: &str) -> &'a str {
+    &start[..start.len() - rest.len()]
+}
+
+// the name and params of a <loop ...> or <grid ...> tag
+fn parse_tag_parts(input: &str) -> IResult<&str, (&str, &str)> {
     let (input, (_x1, _x2, name, params, _x3)) = tuple((
         tag("<"),
         space0,
@@ -160,80 +973,194 @@ fn parse_tag(input: &str) -> IResult<&str, Tag> {
         take_until(">"),
         tag(">"),
     ))(input)?;
+    Ok((input, (name, params)))
+}
 
-    let tag = Tag::new(name, params);
-    Ok((input, tag))
+fn parse_tag(input: &str) -> IResult<&str, Tag> {
+    let start = input;
+    let (input, (name, params)) = parse_tag_parts(input)?;
+    Ok((input, Tag::located(consumed(start, input), name, params)))
 }
 
-fn parse_loop(input: &str) -> IResult<&str, (&str, Vec<ModuleItem>)> {
-    let (input, markdown) = terminated(take_until("</loop>"), tag("</loop>"))(input)?;
-    let loop_input = markdown;
-    println!("in parse_loop: ======= \n loop input:\n{:?}", loop_input);
-    let (_, items) = many0(parse_question_loop_grid)(loop_input)?;
-    println!("items:\n {:?}", items);
+// the whole body of the loop must be questions, loops or grids.
+fn parse_loop_body(input: &str) -> IResult<&str, Vec<ModuleItem>> {
+    let (input, items) = many0(parse_question_loop_grid)(input)?;
+    let (input, _) = parse_whitespace_or_comment(input)?;
+    if !input.is_empty() {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+    Ok((input, items))
+}
 
-    Ok((input, (markdown, items)))
+// The offset of the </name> that closes a <name> whose body starts at input,
+// skipping over any <name>..</name> nested inside it.  Tags in a // comment
+// are skipped the way scan::next_item skips them.
+fn find_closing_tag(input: &str, name: &str) -> Option<usize> {
+    let closing = format!("</{}>", name);
+    let mut depth = 0;
+    // where the current comment ends, tags before it are skipped
+    let mut comment_end = 0;
+    for indx in memchr::memchr2_iter(b'<', b'/', input.as_bytes()) {
+        if indx < comment_end {
+            continue;
+        }
+        let rest = &input[indx..];
+        if rest.starts_with("//") {
+            comment_end = rest.find('\n').map_or(input.len(), |end| indx + end);
+        } else if rest.starts_with(&closing) {
+            if depth == 0 {
+                return Some(indx);
+            }
+            depth -= 1;
+        } else if rest.starts_with('<')
+            && parse_tag_parts(rest).is_ok_and(|(_, (tag, _))| tag == name)
+        {
+            depth += 1;
+        }
+    }
+    None
 }
 
-fn parse_grid(input: &str) -> IResult<&str, &str> {
-    let (input, markdown) = terminated(take_until("</grid>"), tag("</grid>"))(input)?;
-    Ok((input, markdown))
+// the body up to the matching </name>, which is consumed.
+fn take_block_body<'a>(input: &'a str, name: &str) -> IResult<&'a str, &'a str> {
+    match find_closing_tag(input, name) {
+        Some(end) => {
+            let (body, rest) = input.split_at(end);
+            let (rest, _) = tag(&format!("</{}>", name)[..])(rest)?;
+            Ok((rest, body))
+        }
+        None => Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::TakeUntil,
+        ))),
+    }
 }
 
 fn comment(input: &str) -> IResult<&str, &str> {
-    println!("==============================>{}<===============", input);
     let (input, (_, comment, _)) =
         tuple((tag("//"), take_while(|c| c != '\n'), opt(tag("\n"))))(input)?;
     Ok((input, comment))
 }
 
 fn parse_whitespace_or_comment(input: &str) -> IResult<&str, &str> {
-    println!("Parsing input: '{}'", input);
     //let (input, _) = many0(alt((multispace0, comment)))(input)?;
-    let (input, _) = many0(alt((comment, multispace1)))(input)?;
-    println!("Remaining input: '{}'", input);
+    let (input, _) = many0_count(alt((comment, multispace1)))(input)?;
     Ok((input, ""))
 }
 
+// The grammar lives in borrowed, which only slices the input, the owned
+// item is built from the slices it finds.
 fn parse_question_loop_grid(input: &str) -> IResult<&str, ModuleItem> {
-    // nom nom nom any whitespace..
-    println!(
-        "in parse_question_loop_grid: before nomming off ws...\n{}",
-        input
-    );
-    let (input, _) = parse_whitespace_or_comment(input)?;
-    println!(
-        "in parse_question_loop_grid: after nomming off ws...\n{}",
-        input
-    );
-    alt((parse_question, parse_loop_grid))(input)
-}
-
-fn parse_loop_grid(input: &str) -> IResult<&str, ModuleItem> {
-    let (input, tag) = parse_tag(input)?;
-    match &tag.name[..] {
-        "grid" => {
-            let (input, markdown) = parse_grid(input)?;
-            Ok((input, ModuleItem::new_grid(tag, markdown)))
-        }
-        "loop" => {
-            println!("in parse_loop_grid... (loop) \n{}", input);
-            let (input, (markdown, questions)) = parse_loop(input)?;
-            Ok((input, ModuleItem::new_loop(tag, markdown, questions)))
-        }
-        _ => unreachable!(),
-    }
+    let (input, item) = borrowed::parse_item(input)?;
+    Ok((input, item.to_owned_item()))
 }
 
 pub fn parse_module(input: &str) -> IResult<&str, Module> {
+    let source = input;
     let (input, preamble) = take_until_next_module_item(input)?;
 
-    let (input, items) = many0(parse_question_loop_grid)(input)?;
+    let (input, mut items) = many0(parse_question_loop_grid)(input)?;
+    resolve_spans(&mut items, source);
     let m = Module {
         preamble: String::from(preamble),
         items,
     };
-    return Ok((input, m));
+    Ok((input, m))
+}
+
+// Works out why parsing stopped at rest, a sub slice of source.
+fn diagnose(source: &str, rest: &str) -> ParseError {
+    diagnose_on_line(source, rest, &|offset| line_column(source, offset).0)
+}
+
+// diagnose with line_of giving the line an offset into source is on.
+fn diagnose_on_line(source: &str, rest: &str, line_of: &dyn Fn(usize) -> usize) -> ParseError {
+    let offset = offset_of(source, rest);
+    let line = line_of(offset);
+    let message = match parse_tag(rest) {
+        Ok((body, tag)) => match find_closing_tag(body, &tag.name) {
+            Some(end) => match parse_loop_body(&body[..end]) {
+                Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                    return diagnose_on_line(source, e.input, line_of)
+                }
+                _ => format!("invalid <{}>", tag.name),
+            },
+            None => format!("unterminated <{}> opened at line {}", tag.name, line),
+        },
+        Err(_) if rest.starts_with("</loop") || rest.starts_with("</grid") => {
+            format!("unmatched </{}>", &rest[2..6])
+        }
+        Err(_) if rest.starts_with("<loop") || rest.starts_with("<grid") => {
+            format!("unterminated {} tag, missing '>'", &rest[0..5])
+        }
+        Err(_) if rest.starts_with('[') => {
+            String::from("unterminated question header, missing ']'")
+        }
+        Err(_) => String::from("expected a question, <loop> or <grid>"),
+    };
+    ParseError::on_line(source, offset, line, message)
+}
+
+// Parses items until the end of input, when something cannot be parsed a
+// diagnostic is recorded and parsing picks up again at the next question,
+// loop or grid.  A loop with an error in its body keeps the items that did
+// parse.
+fn parse_items_recovering(
+    source: &str,
+    mut input: &str,
+    diagnostics: &mut Vec<ParseError>,
+) -> Vec<ModuleItem> {
+    let mut items = Vec::new();
+    loop {
+        let (rest, parsed) = many0(parse_question_loop_grid)(input).unwrap_or((input, vec![]));
+        items.extend(parsed);
+        let (rest, _) = parse_whitespace_or_comment(rest).unwrap_or((rest, ""));
+        if rest.is_empty() {
+            return items;
+        }
+
+        if let Ok((body, tag)) = parse_tag(rest) {
+            if let (Some(end), "loop") = (find_closing_tag(body, "loop"), &tag.name[..]) {
+                let questions = parse_items_recovering(source, &body[..end], diagnostics);
+                let mut item = ModuleItem::new_loop(tag, &body[..end], questions);
+                input = &body[end + "</loop>".len()..];
+                item.locate_block(consumed(rest, input), &body[..end], &body[end..][..7]);
+                items.push(item);
+                continue;
+            }
+        }
+
+        diagnostics.push(diagnose(source, rest));
+        let skip = rest.chars().next().map_or(0, char::len_utf8);
+        let (next, _) = take_until_next_module_item(&rest[skip..]).unwrap_or(("", ""));
+        input = next;
+    }
+}
+
+impl Module {
+    // Parses a whole module, anything that is not a question, loop or grid
+    // (after the preamble) is an error.  This is borrowed::Module::parse with
+    // the items made owned, so the two always agree.
+    pub fn parse(input: &str) -> Result<Module, ParseError> {
+        borrowed::Module::parse(input).map(borrowed::Module::into_owned)
+    }
+
+    // Parses as much of the module as possible, returning every problem
+    // found instead of stopping at the first one.
+    pub fn parse_recovering(input: &str) -> (Module, Vec<ParseError>) {
+        let mut diagnostics = Vec::new();
+        let (rest, preamble) = take_until_next_module_item(input).unwrap_or(("", input));
+        let mut items = parse_items_recovering(input, rest, &mut diagnostics);
+        resolve_spans(&mut items, input);
+        let module = Module {
+            preamble: String::from(preamble),
+            items,
+        };
+        (module, diagnostics)
+    }
 }
 
 #[cfg(test)]
@@ -241,7 +1168,7 @@ mod tests {
     use super::*;
     #[allow(unused_imports)]
     use nom::{character::complete::alpha0, error::Error};
-    use nom::{character::complete::multispace0, sequence::preceded, Err};
+    use nom::{multi::many1, sequence::delimited, Err};
     use regex::Regex;
 
     #[test]
@@ -276,6 +1203,165 @@ mod tests {
         assert_eq!(current, "¿Cuántas comidas comiste hoy?");
     }
 
+    #[test]
+    fn test_parse_header() {
+        let header = QuestionHeader::new("Q1");
+        assert_eq!(header.id, "Q1");
+        assert!(!header.soft_required && !header.hard_required);
+        assert!(header.attributes.is_empty());
+
+        let header = QuestionHeader::new(r#"Q2? displayif="lala" min=0,max=120"#);
+        assert_eq!(header.id, "Q2");
+        assert!(header.soft_required);
+        assert!(!header.hard_required);
+        assert_eq!(header.attribute("displayif"), Some("lala"));
+        assert_eq!(header.attribute("min"), Some("0"));
+        assert_eq!(header.attribute("max"), Some("120"));
+
+        let header = QuestionHeader::new(
+            r#"D_123! displayif=or(equals(Q1, 1),equals(Q2,[1, 2])) id='a [b] (c)'"#,
+        );
+        assert_eq!(header.id, "D_123");
+        assert!(header.hard_required);
+        assert_eq!(
+            header.attribute("displayif"),
+            Some("or(equals(Q1, 1),equals(Q2,[1, 2]))")
+        );
+        assert_eq!(header.attribute("id"), Some("a [b] (c)"));
+
+        // the first '=' splits the key from the value.
+        let header = QuestionHeader::new("Q1 displayif=q=1");
+        assert_eq!(header.attribute("displayif"), Some("q=1"));
+        assert!(header.displayif().unwrap().is_err());
+
+        let header = QuestionHeader::new(r#"Q1! displayif=equals(Q2, 1) label="a b" x='"'"#);
+        assert_eq!(
+            header.to_string(),
+            r#"Q1! displayif=equals(Q2, 1) label="a b" x='"'"#
+        );
+        assert_eq!(QuestionHeader::new(&header.to_string()), header);
+
+        let header = QuestionHeader::new("Q1 displayif=isDefined(Q2)");
+        assert_eq!(
+            header.displayif(),
+            Some(Ok(Expr::call("isDefined", vec![Expr::ident("Q2")])))
+        );
+    }
+
+    #[test]
+    fn test_parse_responses() {
+        let q = Question::new("Q1", "How are you?\n(1) Good\n(2) Bad -- really bad\n");
+        assert_eq!(q.text, "How are you?");
+        assert_eq!(
+            q.responses,
+            vec![
+                Response::new(ResponseKind::Radio, "1", "Good"),
+                Response::new(ResponseKind::Radio, "2", "Bad -- really bad"),
+            ]
+        );
+
+        let q = Question::new(
+            "Q2",
+            "Pick some (Select all that apply)\n[12] A\n  [13 displayif=equals(Q1,1)] B",
+        );
+        assert_eq!(q.text, "Pick some (Select all that apply)");
+        assert_eq!(q.responses.len(), 2);
+        assert_eq!(q.responses[0].kind, ResponseKind::Checkbox);
+        assert_eq!(q.responses[1].value, "13");
+        assert_eq!(q.responses[1].label, "B");
+        assert_eq!(
+            q.responses[1]
+                .attributes
+                .get("displayif")
+                .map(|v| v.as_str()),
+            Some("equals(Q1,1)")
+        );
+
+        let q = Question::new("Q3", "No responses here.");
+        assert_eq!(q.text, "No responses here.");
+        assert!(q.responses.is_empty());
+    }
+
+    #[test]
+    fn test_parse_inputs() {
+        let inputs = find_inputs("Name |__| and age |__|__|id=AGE min=0 max=120| years");
+        assert_eq!(inputs.len(), 2);
+        assert_eq!(inputs[0].field, InputField::Text);
+        assert_eq!(inputs[0].id, None);
+        assert_eq!(
+            inputs[1].field,
+            InputField::Number {
+                min: Some(String::from("0")),
+                max: Some(String::from("120"))
+            }
+        );
+        assert_eq!(inputs[1].id.as_deref(), Some("AGE"));
+
+        let inputs = find_inputs("|__|id=x min=0 max=120| |date| |month| |email| |tel| |___|");
+        let fields: Vec<_> = inputs.iter().map(|i| i.field.clone()).collect();
+        assert_eq!(
+            fields,
+            vec![
+                InputField::Number {
+                    min: Some(String::from("0")),
+                    max: Some(String::from("120"))
+                },
+                InputField::Date,
+                InputField::Month,
+                InputField::Email,
+                InputField::Tel,
+                InputField::TextBox,
+            ]
+        );
+        assert!(find_inputs("a | b | c").is_empty());
+
+        let q = Question::new("Q1", "How old are you? |__|__|\n(77) Other: |__|id=OTHER|");
+        assert_eq!(q.inputs.len(), 1);
+        assert_eq!(q.responses[0].inputs.len(), 1);
+        assert_eq!(q.responses[0].inputs[0].id.as_deref(), Some("OTHER"));
+    }
+
+    #[test]
+    fn test_parse_goto() {
+        let q = Question::new(
+            "Q1",
+            "Do you smoke?\n(1) Yes -> Q3\n(0) No ->  Q5\n(2) Sometimes\n#NR -> Q9",
+        );
+        assert_eq!(q.text, "Do you smoke?");
+        let gotos: Vec<_> = q.responses.iter().map(|r| r.goto.as_deref()).collect();
+        assert_eq!(gotos, vec![Some("Q3"), Some("Q5"), None]);
+        assert_eq!(q.responses[0].label, "Yes");
+        assert_eq!(q.no_response_goto.as_deref(), Some("Q9"));
+        assert_eq!(q.goto, None);
+
+        let q = Question::new("Q2", "How old are you? |__| -> Q4\n#NR -> END");
+        assert_eq!(q.text, "How old are you? |__|");
+        assert_eq!(q.goto.as_deref(), Some("Q4"));
+        assert_eq!(q.no_response_goto.as_deref(), Some("END"));
+        assert_eq!(q.inputs.len(), 1);
+
+        // an arrow in the middle of the text is not a skip.
+        let q = Question::new("Q3", "a -> b is an arrow");
+        assert_eq!(q.goto, None);
+        assert_eq!(q.text, "a -> b is an arrow");
+    }
+
+    fn parse_loop_grid(input: &str) -> IResult<&str, ModuleItem> {
+        let (input, item) = borrowed::parse_loop_grid(input)?;
+        Ok((input, item.to_owned_item()))
+    }
+
+    // Parsed items know where they came from, items made by hand do not (or
+    // point at a test literal), so comparisons leave the spans out.
+    fn without_spans(result: IResult<&str, ModuleItem>) -> IResult<&str, ModuleItem> {
+        result.map(|(rest, mut item)| {
+            for span in item.spans_mut() {
+                *span = Span::default();
+            }
+            (rest, item)
+        })
+    }
+
     #[test]
     fn test_parse_tag() {
         let r = parse_tag("<fail>[Q1]lala\n[Q2]lili</fail>");
@@ -284,7 +1370,7 @@ mod tests {
         let input = r#"<grid id="gridid"> [GID lasdf]"#;
         let (input, tag) = parse_tag(input).unwrap();
         assert_eq!(input, " [GID lasdf]");
-        assert_eq!(tag, Tag::new("grid", r#"id="gridid""#));
+        assert_eq!((&tag.name[..], &tag.params[..]), ("grid", r#"id="gridid""#));
     }
 
     #[test]
@@ -310,8 +1396,8 @@ mod tests {
     #[test]
     fn test_loop() {
         assert_eq!(
-            parse_loop_grid("<loop>[Q1]lala\n[Q2]lili</loop>"),
-            Ok((
+            without_spans(parse_loop_grid("<loop>[Q1]lala\n[Q2]lili</loop>")),
+            without_spans(Ok((
                 "",
                 ModuleItem::new_loop(
                     Tag::new("loop", ""),
@@ -321,17 +1407,155 @@ mod tests {
                         ModuleItem::new_question("Q2", "lili")
                     ]
                 )
-            ))
+            )))
         );
     }
+    #[test]
+    fn test_loop_expand() {
+        let (_, item) = parse_loop_grid(
+            "<loop max=2 firstquestion=L1 displayif=isDefined(Q1) counter=Q1>\n\
+             [L1] Name of person #loop?\n(1) Yes -> L3\n(2) No -> Q9\n\
+             [L2 displayif=equals(L1_#loop,1)] Why?\n\
+             [L3] Done</loop>",
+        )
+        .unwrap();
+        let ModuleItem::Loop(l) = item else {
+            panic!("expected a loop");
+        };
+        assert_eq!(l.max, Some(2));
+        assert_eq!(l.first_question.as_deref(), Some("L1"));
+        assert_eq!(l.displayif.as_deref(), Some("isDefined(Q1)"));
+        assert_eq!(l.counter.as_deref(), Some("Q1"));
+
+        // max caps the number of iterations.
+        let items = l.expand(5);
+        let ids: Vec<_> = items
+            .iter()
+            .map(|item| match item {
+                ModuleItem::Question(q) => q.id(),
+                _ => panic!("expected a question"),
+            })
+            .collect();
+        assert_eq!(ids, vec!["L1_1", "L2_1", "L3_1", "L1_2", "L2_2", "L3_2"]);
+
+        let ModuleItem::Question(l1_2) = &items[3] else {
+            panic!("expected a question");
+        };
+        assert_eq!(l1_2.text, "Name of person 2?");
+        assert_eq!(l1_2.responses[0].goto.as_deref(), Some("L3_2"));
+        assert_eq!(l1_2.responses[1].goto.as_deref(), Some("Q9"));
+        let ModuleItem::Question(l2_2) = &items[4] else {
+            panic!("expected a question");
+        };
+        assert_eq!(l2_2.header.attribute("displayif"), Some("equals(L1_2,1)"));
+        assert!(l.expand(0).is_empty());
+    }
+
+    #[test]
+    fn test_nested_loops() {
+        let markdown = "<loop id=HH max=2>\n\
+                        [P1] Name of person #loop?\n\
+                        <grid id=G>\n(1) Yes\n(2) No\n[G_1] Works? |__|id=G_HOURS|\n</grid>\n\
+                        <loop id=JOBS max=3>\n[J1] Job? |__|id=EMPLOYER|\n</loop>\n\
+                        [P2] Anyone else?\n\
+                        </loop>\n\
+                        [Q2] after the loop";
+        let module = Module::parse(markdown).unwrap();
+        assert_eq!(module.items.len(), 2);
+        let ModuleItem::Loop(l) = &module.items[0] else {
+            panic!("expected a loop");
+        };
+        let kinds: Vec<_> = l
+            .questions()
+            .iter()
+            .map(|item| match item {
+                ModuleItem::Question(q) => q.id(),
+                ModuleItem::Loop(_) => "loop",
+                ModuleItem::Grid(_) => "grid",
+            })
+            .collect();
+        assert_eq!(kinds, vec!["P1", "grid", "loop", "P2"]);
+        let ModuleItem::Loop(inner) = &l.questions()[2] else {
+            panic!("expected a loop");
+        };
+        assert_eq!(inner.id.as_deref(), Some("JOBS"));
+        assert_eq!(
+            &markdown[inner.span.range()],
+            "<loop id=JOBS max=3>\n[J1] Job? |__|id=EMPLOYER|\n</loop>"
+        );
+        assert!(l.markdown().ends_with("[P2] Anyone else?"));
+
+        let symbols = module.symbols();
+        // grids, rows, inputs and inner loops get an id per iteration
+        for id in [
+            "G_2",
+            "G_1_2",
+            "G_HOURS_2",
+            "JOBS_2",
+            "P2_2",
+            "J1_2_3",
+            "EMPLOYER_2_3",
+        ] {
+            assert!(symbols.contains(id), "{} is missing", id);
+        }
+        assert!(symbols.collisions().is_empty());
+
+        // an unclosed inner loop leaves the outer loop unterminated
+        let e = Module::parse("<loop>\n[L1] a\n<loop>\n[L2] b\n</loop>").unwrap_err();
+        assert_eq!(e.message, "unterminated <loop> opened at line 1");
+        let (module, errors) =
+            Module::parse_recovering("<loop>\n<loop>\n[L1] a\n</grid>\n</loop>\n</loop>\n[Q1] b");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(module.items.len(), 2);
+
+        // tags in a comment neither open nor close a loop
+        let body = "[L1] a // <loop>\n//[Q1]  </loop>\n</loop> [Q2]";
+        assert_eq!(find_closing_tag(body, "loop"), Some(33));
+        assert_eq!(find_closing_tag("//</loop>", "loop"), None);
+    }
+
+    #[test]
+    fn test_grid_rows_and_columns() {
+        let (_, item) = parse_loop_grid(
+            "<grid id=\"G1\" displayif=equals(Q1,1)>\n\
+             How much do you like...\n\
+             (1) A lot\n(2) Some\n(3) Not at all\n\
+             [G1_1] apples\n[G1_2 displayif=equals(Q2,1)] pears\n</grid>",
+        )
+        .unwrap();
+        let ModuleItem::Grid(g) = item else {
+            panic!("expected a grid");
+        };
+        assert_eq!(g.id.as_deref(), Some("G1"));
+        assert_eq!(g.displayif.as_deref(), Some("equals(Q1,1)"));
+        assert_eq!(g.prompt, "How much do you like...");
+        assert_eq!(g.kind, ResponseKind::Radio);
+        let values: Vec<_> = g.columns.iter().map(|c| c.value.as_str()).collect();
+        assert_eq!(values, vec!["1", "2", "3"]);
+        let rows: Vec<_> = g.rows.iter().map(|r| (r.id(), r.text.as_str())).collect();
+        assert_eq!(rows, vec![("G1_1", "apples"), ("G1_2", "pears")]);
+        assert!(g.rows[1].header.displayif().is_some());
+        assert_eq!(g.row_questions()[0].responses.len(), 3);
+
+        // columns after the rows, checkboxes
+        let grid = Grid::new(
+            Tag::new("grid", ""),
+            "[G1] first row\n[G2] second row\n[1] A\n[2] B",
+        );
+        assert_eq!(grid.prompt, "");
+        assert_eq!(grid.kind, ResponseKind::Checkbox);
+        assert_eq!(grid.columns.len(), 2);
+        assert!(grid.rows.iter().all(|r| r.responses.is_empty()));
+    }
+
     #[test]
     fn test_grid() {
         assert_eq!(
-            parse_loop_grid("<grid>[Q1]lala\n[Q2]lili</grid>"),
-            Ok((
+            without_spans(parse_loop_grid("<grid>[Q1]lala\n[Q2]lili</grid>")),
+            without_spans(Ok((
                 "",
                 ModuleItem::new_grid(Tag::new("grid", ""), "[Q1]lala\n[Q2]lili")
-            ))
+            )))
         );
     }
 
@@ -339,7 +1563,10 @@ mod tests {
     fn test_parse_item() {
         let markdown = "[Q1] this is a test [Q2] end!!";
         let mi = ModuleItem::new_question("Q1", "this is a test");
-        assert_eq!(parse_question_loop_grid(markdown), Ok(("[Q2] end!!", mi)));
+        assert_eq!(
+            without_spans(parse_question_loop_grid(markdown)),
+            Ok(("[Q2] end!!", mi))
+        );
 
         let markdown = "<loop>\n[A1]this is a test [Q2] end!!</loop>";
         let mi = ModuleItem::new_loop(
@@ -350,7 +1577,10 @@ mod tests {
                 ModuleItem::new_question("Q2", "end!!"),
             ],
         );
-        assert_eq!(parse_question_loop_grid(markdown), Ok(("", mi)));
+        assert_eq!(
+            without_spans(parse_question_loop_grid(markdown)),
+            without_spans(Ok(("", mi)))
+        );
 
         let markdown = "<loop id=\"loopid\">\n[A1]this is a test [Q2] end!!</loop>";
         let mi = ModuleItem::new_loop(
@@ -362,11 +1592,17 @@ mod tests {
             ],
         );
         println!("{:?}", parse_question_loop_grid(markdown));
-        assert_eq!(parse_question_loop_grid(markdown), Ok(("", mi)));
+        assert_eq!(
+            without_spans(parse_question_loop_grid(markdown)),
+            without_spans(Ok(("", mi)))
+        );
 
         let markdown = "<grid>\n[A1]this is a test [Q2] end!!\n</grid>";
         let mi = ModuleItem::new_grid(Tag::new("grid", ""), "[A1]this is a test [Q2] end!!");
-        assert_eq!(parse_question_loop_grid(markdown), Ok(("", mi)));
+        assert_eq!(
+            without_spans(parse_question_loop_grid(markdown)),
+            without_spans(Ok(("", mi)))
+        );
     }
 
     #[test]
@@ -399,6 +1635,121 @@ mod tests {
         println!("remaining input:\n {}", input);
     }
 
+    #[test]
+    fn test_module_parse_errors() {
+        let module = Module::parse("intro\n[Q1] hi\n<loop>[L1] a</loop>\n// done\n").unwrap();
+        assert_eq!(module.items.len(), 2);
+
+        let e = Module::parse("[Q1] hi\n[Q2] there\n<loop max=2>\n[L1] a\n").unwrap_err();
+        assert_eq!(e.message, "unterminated <loop> opened at line 3");
+        assert_eq!((e.line, e.column, e.offset), (3, 1, 19));
+        assert_eq!(e.snippet, "3 | <loop max=2>\n  | ^");
+
+        let e = Module::parse("[Q1] hi\n</loop>\n[Q2] there").unwrap_err();
+        assert_eq!(e.message, "unmatched </loop>");
+        assert_eq!(e.line, 2);
+
+        let e = Module::parse("[Q1] hi\n<grid id=G1>\n[G1] row").unwrap_err();
+        assert_eq!(e.message, "unterminated <grid> opened at line 2");
+
+        let e = Module::parse("[Q1] hi\n<loop max=2\n").unwrap_err();
+        assert_eq!(e.message, "unterminated <loop tag, missing '>'");
+
+        // a commented out closing tag does not close the loop
+        let e = Module::parse("[Q1] hi\n<loop max=2>\n//[L1]  </loop>\n").unwrap_err();
+        assert_eq!(e.message, "unterminated <loop> opened at line 2");
+        assert_eq!(e.line, 2);
+
+        // the error inside the loop body is reported, not the loop.
+        let e = Module::parse("[Q1] hi\n<loop>\n[L1] a\n  </grid>\n</loop>").unwrap_err();
+        assert_eq!(e.message, "unmatched </grid>");
+        assert_eq!((e.line, e.column), (4, 3));
+    }
+
+    #[test]
+    fn test_parse_recovering() {
+        let markdown = "[Q1] one\n\
+                        </grid>\n\
+                        [Q2] two\n\
+                        <loop>\n[L1] a\n</grid>\n[L2] b\n</loop>\n\
+                        [Q3] three\n\
+                        <grid id=G1>\n[G1] never closed\n\
+                        [Q4] four";
+        let (module, diagnostics) = Module::parse_recovering(markdown);
+        let messages: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.line, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (2, "unmatched </grid>"),
+                (6, "unmatched </grid>"),
+                (10, "unterminated <grid> opened at line 10"),
+            ]
+        );
+        let ids: Vec<_> = module
+            .items
+            .iter()
+            .map(|item| match item {
+                ModuleItem::Question(q) => q.id(),
+                ModuleItem::Loop(_) => "loop",
+                ModuleItem::Grid(_) => "grid",
+            })
+            .collect();
+        assert_eq!(ids, vec!["Q1", "Q2", "loop", "Q3", "G1", "Q4"]);
+        let ModuleItem::Loop(l) = &module.items[2] else {
+            panic!("expected a loop");
+        };
+        assert_eq!(l.questions().len(), 2);
+
+        assert_eq!(Module::parse(markdown).unwrap_err(), diagnostics[0]);
+    }
+
+    #[test]
+    fn test_spans() {
+        let markdown = "intro\n[Q1 min=0] one\n\n<loop max=2>\n  [L1] a\n</loop>\n<grid id=G>\n[G1] r\n</grid>";
+        let module = Module::parse(markdown).unwrap();
+        let text = |span: Span| &markdown[span.range()];
+
+        let ModuleItem::Question(q1) = &module.items[0] else {
+            panic!("expected a question");
+        };
+        assert_eq!(text(q1.span), "[Q1 min=0] one");
+        assert_eq!(text(q1.header_span), "Q1 min=0");
+        assert_eq!(text(q1.markdown_span), "one");
+        assert_eq!((q1.span.line, q1.span.column), (2, 1));
+
+        let ModuleItem::Loop(l) = &module.items[1] else {
+            panic!("expected a loop");
+        };
+        assert_eq!(text(l.span), "<loop max=2>\n  [L1] a\n</loop>");
+        assert_eq!(text(l.tag().span), "<loop max=2>");
+        assert_eq!(text(l.tag().params_span), "max=2");
+        assert_eq!(text(l.body_span), "[L1] a");
+        assert_eq!(text(l.close_span), "</loop>");
+        assert_eq!((l.close_span.line, l.close_span.column), (6, 1));
+        let l1 = l.questions()[0].span();
+        assert_eq!((text(l1), l1.line, l1.column), ("[L1] a", 5, 3));
+
+        // expanded copies point back at the original.
+        let expanded = l.expand(2);
+        assert_eq!(text(expanded[1].span()), "[L1] a");
+
+        let ModuleItem::Grid(g) = &module.items[2] else {
+            panic!("expected a grid");
+        };
+        assert_eq!(text(g.close_span), "</grid>");
+        assert_eq!(text(g.rows[0].span), "[G1] r");
+        assert_eq!(g.rows[0].span.line, 8);
+
+        // the same spans come out of the nom api and the recovering parser.
+        let (_, module) = parse_module(markdown).unwrap();
+        assert_eq!(text(module.items[1].span()), text(l.span));
+        let (module, _) = Module::parse_recovering("<loop>[L1] a </grid></loop>");
+        assert_eq!(module.items[0].span().range(), 0..27);
+    }
+
     #[test]
     fn test_read_module() {
         let text = r#"
@@ -443,6 +1794,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_d1() {
         let input = r#"       
         [Q1] Question
@@ -476,6 +1828,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants, clippy::while_let_on_iterator)]
     fn peeking() {
         let input = r#"
         // this is a comment
@@ -0,0 +1,529 @@
+use crate::{Expr, Grid, Loop, Module, ModuleItem, ParseError, Question, Span, SymbolTable};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum Rule {
+    // two questions with the same id
+    DuplicateId,
+    // a -> or #NR -> to an id that is not in the module
+    UnknownSkipTarget,
+    // a displayif that uses a question id that is not in the module
+    UnknownReference,
+    // a question with nothing to answer, or a grid without columns
+    EmptyResponses,
+    // (1) Yes and (1) No in the same question
+    DuplicateResponseValue,
+    // no path from the first question leads to it
+    UnreachableQuestion,
+    LoopWithoutMax,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::DuplicateId,
+        Rule::UnknownSkipTarget,
+        Rule::UnknownReference,
+        Rule::EmptyResponses,
+        Rule::DuplicateResponseValue,
+        Rule::UnreachableQuestion,
+        Rule::LoopWithoutMax,
+    ];
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Rule::DuplicateId => "duplicate-id",
+            Rule::UnknownSkipTarget => "unknown-skip-target",
+            Rule::UnknownReference => "unknown-reference",
+            Rule::EmptyResponses => "empty-responses",
+            Rule::DuplicateResponseValue => "duplicate-response-value",
+            Rule::UnreachableQuestion => "unreachable-question",
+            Rule::LoopWithoutMax => "loop-without-max",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|rule| rule.id() == id)
+    }
+
+    pub fn default_severity(&self) -> Severity {
+        match self {
+            Rule::DuplicateId | Rule::UnknownSkipTarget => Severity::Error,
+            Rule::UnknownReference
+            | Rule::DuplicateResponseValue
+            | Rule::UnreachableQuestion
+            | Rule::LoopWithoutMax => Severity::Warning,
+            // text only questions are common, e.g. an introduction
+            Rule::EmptyResponses => Severity::Info,
+        }
+    }
+}
+
+// Which rules run and how severe their diagnostics are.  Every rule is on at
+// its default severity unless the config says otherwise.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LintConfig {
+    // None turns the rule off
+    overrides: HashMap<Rule, Option<Severity>>,
+}
+
+impl LintConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn severity(&self, rule: Rule) -> Option<Severity> {
+        match self.overrides.get(&rule) {
+            Some(severity) => *severity,
+            None => Some(rule.default_severity()),
+        }
+    }
+
+    pub fn set_severity(&mut self, rule: Rule, severity: Severity) {
+        self.overrides.insert(rule, Some(severity));
+    }
+
+    pub fn disable(&mut self, rule: Rule) {
+        self.overrides.insert(rule, None);
+    }
+
+    pub fn enable(&mut self, rule: Rule) {
+        self.overrides.remove(&rule);
+    }
+
+    pub fn is_enabled(&self, rule: Rule) -> bool {
+        self.severity(rule).is_some()
+    }
+
+    // One rule per line, # starts a comment:
+    //
+    // loop-without-max = off
+    // unreachable-question = error
+    pub fn parse(config: &str) -> Result<LintConfig, ParseError> {
+        let mut lint_config = LintConfig::new();
+        let mut offset = 0;
+        for line in config.split_inclusive('\n') {
+            let setting = line.split('#').next().unwrap_or_default();
+            if let Some((id, level)) = setting.split_once('=') {
+                let rule = Rule::from_id(id.trim()).ok_or_else(|| {
+                    let message = format!("unknown lint rule '{}'", id.trim());
+                    ParseError::new(config, offset, message)
+                })?;
+                match level.trim() {
+                    "off" => lint_config.disable(rule),
+                    "info" => lint_config.set_severity(rule, Severity::Info),
+                    "warning" => lint_config.set_severity(rule, Severity::Warning),
+                    "error" => lint_config.set_severity(rule, Severity::Error),
+                    level => {
+                        let message =
+                            format!("expected off, info, warning or error, found '{}'", level);
+                        return Err(ParseError::new(config, offset, message));
+                    }
+                }
+            } else if !setting.trim().is_empty() {
+                return Err(ParseError::new(config, offset, "expected rule = level"));
+            }
+            offset += line.len();
+        }
+        Ok(lint_config)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostic {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub message: String,
+    // the question, loop or grid the problem is in
+    pub span: Span,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}]: {} at line {}, column {}",
+            self.severity,
+            self.rule.id(),
+            self.message,
+            self.span.line,
+            self.span.column
+        )
+    }
+}
+
+// every question in document order, including loop bodies and grid rows
+fn questions(items: &[ModuleItem]) -> Vec<&Question> {
+    let mut questions = Vec::new();
+    for item in items {
+        match item {
+            ModuleItem::Question(q) => questions.push(q),
+            ModuleItem::Loop(l) => questions.extend(self::questions(l.questions())),
+            ModuleItem::Grid(g) => questions.extend(g.rows.iter()),
+        }
+    }
+    questions
+}
+
+fn loops(items: &[ModuleItem]) -> Vec<&Loop> {
+    let mut loops = Vec::new();
+    for item in items {
+        if let ModuleItem::Loop(l) = item {
+            loops.push(l);
+            loops.extend(self::loops(l.questions()));
+        }
+    }
+    loops
+}
+
+fn grids(items: &[ModuleItem]) -> Vec<&Grid> {
+    let mut grids = Vec::new();
+    for item in items {
+        match item {
+            ModuleItem::Grid(g) => grids.push(g),
+            ModuleItem::Loop(l) => grids.extend(self::grids(l.questions())),
+            ModuleItem::Question(_) => {}
+        }
+    }
+    grids
+}
+
+// the ids a skip arrow or displayif may use: every id in the symbol table,
+// and the ids in loop bodies before they are expanded.
+fn known_ids(symbols: &SymbolTable, questions: &[&Question]) -> HashSet<String> {
+    let mut ids: HashSet<String> = symbols.symbols().iter().map(|s| s.id.clone()).collect();
+    ids.extend(questions.iter().map(|q| String::from(q.id())));
+    ids
+}
+
+// the identifiers in expr that must be question ids.  equals(Q1,yes) compares
+// Q1 to the word yes, so only the first argument of a comparison counts.
+fn referenced_ids<'a>(expr: &'a Expr, ids: &mut Vec<&'a str>) {
+    let Expr::Call { name, args } = expr else {
+        return;
+    };
+    let (references, rest): (&[Expr], &[Expr]) = match name.as_str() {
+        "isDefined" | "exists" | "isNotDefined" | "doesNotExist" | "noneExist" | "someExist"
+        | "allExist" => (args, &[]),
+        "and" | "or" | "not" | "min" | "max" | "sum" | "difference" | "dateCompare"
+        | "percentDiff" => (&[], args),
+        _ => args.split_at(args.len().min(1)),
+    };
+    for arg in references {
+        match arg {
+            Expr::Ident(id) => ids.push(id),
+            _ => referenced_ids(arg, ids),
+        }
+    }
+    for arg in rest {
+        referenced_ids(arg, ids);
+    }
+}
+
+struct Linter<'a> {
+    config: &'a LintConfig,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, rule: Rule, span: Span, message: String) {
+        if let Some(severity) = self.config.severity(rule) {
+            self.diagnostics.push(Diagnostic {
+                rule,
+                severity,
+                message,
+                span,
+            });
+        }
+    }
+
+    // collisions between expanded loop ids, grid rows and inputs included
+    fn duplicate_ids(&mut self, symbols: &SymbolTable) {
+        for collision in symbols.collisions() {
+            self.report(
+                Rule::DuplicateId,
+                collision.second.span,
+                format!(
+                    "duplicate id {}, first used at line {}",
+                    collision.first.id, collision.first.span.line
+                ),
+            );
+        }
+    }
+
+    fn skip_targets(&mut self, questions: &[&Question], known: &HashSet<String>) {
+        for question in questions {
+            let targets = question
+                .responses
+                .iter()
+                .filter_map(|r| r.goto.as_ref())
+                .chain(&question.goto)
+                .chain(&question.no_response_goto);
+            for target in targets {
+                if target != "END" && !known.contains(target) {
+                    self.report(
+                        Rule::UnknownSkipTarget,
+                        question.span,
+                        format!(
+                            "{} skips to {}, which is not in the module",
+                            question.id(),
+                            target
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    fn references(&mut self, questions: &[&Question], known: &HashSet<String>) {
+        for question in questions {
+            let Some(Ok(expr)) = question.header.displayif() else {
+                continue;
+            };
+            let mut ids = Vec::new();
+            referenced_ids(&expr, &mut ids);
+            for id in ids {
+                // D_123.D_456 refers to D_123
+                let question_id = id.split('.').next().unwrap_or(id);
+                if !known.contains(question_id) {
+                    self.report(
+                        Rule::UnknownReference,
+                        question.header_span,
+                        format!(
+                            "the displayif of {} uses {}, which is not in the module",
+                            question.id(),
+                            id
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    fn responses(&mut self, module: &Module, questions: &[&Question]) {
+        let grids = grids(&module.items);
+        // the responses of a grid row are the columns of its grid
+        let rows: HashSet<*const Question> = grids
+            .iter()
+            .flat_map(|g| g.rows.iter())
+            .map(|row| row as *const Question)
+            .collect();
+        for question in questions {
+            let is_row = rows.contains(&(*question as *const Question));
+            if question.responses.is_empty() && question.inputs.is_empty() && !is_row {
+                self.report(
+                    Rule::EmptyResponses,
+                    question.span,
+                    format!("{} has no responses or inputs", question.id()),
+                );
+            }
+            let mut values = HashSet::new();
+            for response in &question.responses {
+                if !values.insert(&response.value) {
+                    self.report(
+                        Rule::DuplicateResponseValue,
+                        question.span,
+                        format!(
+                            "{} has more than one response with the value {}",
+                            question.id(),
+                            response.value
+                        ),
+                    );
+                }
+            }
+        }
+        for g in grids {
+            if g.columns.is_empty() {
+                let id = g.id.as_deref().unwrap_or("grid");
+                self.report(
+                    Rule::EmptyResponses,
+                    g.span,
+                    format!("{} has no columns", id),
+                );
+            }
+        }
+    }
+
+    fn unreachable(&mut self, module: &Module, questions: &[&Question]) {
+        let graph = module.navigation_graph();
+        let Some(first) = graph.nodes.first() else {
+            return;
+        };
+        let mut reached: HashSet<&str> = HashSet::from([first.as_str()]);
+        let mut queue = VecDeque::from([first.as_str()]);
+        while let Some(id) = queue.pop_front() {
+            for next in graph.successors(id) {
+                if reached.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+        let mut reported = HashSet::new();
+        for question in questions {
+            let id = question.id();
+            // a duplicated id is only reported once
+            if !reached.contains(id) && reported.insert(id) {
+                self.report(
+                    Rule::UnreachableQuestion,
+                    question.span,
+                    format!("{} cannot be reached from {}", id, first),
+                );
+            }
+        }
+    }
+
+    fn loops(&mut self, module: &Module) {
+        for l in loops(&module.items) {
+            if l.max.is_none() {
+                let id = l.id.as_deref().unwrap_or("loop");
+                self.report(
+                    Rule::LoopWithoutMax,
+                    l.tag().span,
+                    format!("{} has no max, it runs once", id),
+                );
+            }
+        }
+    }
+}
+
+// Runs the enabled rules over a module, the diagnostics are in document
+// order.
+pub fn lint(module: &Module, config: &LintConfig) -> Vec<Diagnostic> {
+    let mut linter = Linter {
+        config,
+        diagnostics: Vec::new(),
+    };
+    let questions = questions(&module.items);
+    let symbols = module.symbols();
+    let known = known_ids(&symbols, &questions);
+    linter.duplicate_ids(&symbols);
+    linter.skip_targets(&questions, &known);
+    linter.references(&questions, &known);
+    linter.responses(module, &questions);
+    linter.unreachable(module, &questions);
+    linter.loops(module);
+
+    let mut diagnostics = linter.diagnostics;
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULE: &str = r#"[Q1] Do you smoke?
+(1) Yes
+(1) No -> Q9
+[Q2 displayif=equals(Q1,yes)] How many? |__|id=HOW_MANY| -> Q4
+[Q3 displayif=and(isDefined(HOW_MANY),greaterThan(Q8,1))] Orphan
+(1) a
+<loop>
+[L1] loop question |__|
+<grid id=LG>
+[LG_1] no columns
+</grid>
+</loop>
+[Q4] Any more?
+<grid id=G>
+How often?
+(1) Never
+(2) Always
+[G_1] fruit
+[G_2] vegetables
+</grid>
+[Q2] Again? |__|
+#NR -> END"#;
+
+    fn summary(diagnostics: &[Diagnostic]) -> Vec<(&'static str, usize)> {
+        diagnostics
+            .iter()
+            .map(|d| (d.rule.id(), d.span.line))
+            .collect()
+    }
+
+    #[test]
+    fn test_lint_rules() {
+        let module = Module::parse(MODULE).unwrap();
+        let diagnostics = lint(&module, &LintConfig::new());
+        assert_eq!(
+            summary(&diagnostics),
+            vec![
+                ("unknown-skip-target", 1),
+                ("duplicate-response-value", 1),
+                ("unreachable-question", 5),
+                ("unknown-reference", 5),
+                ("loop-without-max", 7),
+                ("unreachable-question", 8),
+                ("empty-responses", 9),
+                ("unreachable-question", 10),
+                ("empty-responses", 13),
+                ("duplicate-id", 21),
+            ]
+        );
+        assert_eq!(
+            diagnostics[3].to_string(),
+            "warning[unknown-reference]: the displayif of Q3 uses Q8, which is not in the module at line 5, column 2"
+        );
+        assert_eq!(
+            diagnostics[9].message,
+            "duplicate id Q2, first used at line 4"
+        );
+        assert_eq!(diagnostics[9].severity, Severity::Error);
+        // the rows of G take the columns of the grid, LG in the loop has none
+        assert_eq!(diagnostics[6].message, "LG has no columns");
+    }
+
+    #[test]
+    fn test_lint_config() {
+        let config = LintConfig::parse(
+            "# quiet\nunreachable-question = off\nempty-responses=error # really\n",
+        )
+        .unwrap();
+        assert!(!config.is_enabled(Rule::UnreachableQuestion));
+        assert_eq!(config.severity(Rule::EmptyResponses), Some(Severity::Error));
+        assert_eq!(config.severity(Rule::DuplicateId), Some(Severity::Error));
+
+        let module = Module::parse(MODULE).unwrap();
+        let diagnostics = lint(&module, &config);
+        assert!(diagnostics
+            .iter()
+            .all(|d| d.rule != Rule::UnreachableQuestion));
+        assert!(diagnostics
+            .iter()
+            .any(|d| d.rule == Rule::EmptyResponses && d.severity == Severity::Error));
+
+        let e = LintConfig::parse("duplicate-id = error\nno-such-rule = off").unwrap_err();
+        assert_eq!(
+            (e.line, e.message.as_str()),
+            (2, "unknown lint rule 'no-such-rule'")
+        );
+        let e = LintConfig::parse("duplicate-id = loud").unwrap_err();
+        assert_eq!(
+            e.message,
+            "expected off, info, warning or error, found 'loud'"
+        );
+    }
+}
@@ -1,65 +1,461 @@
-use nom1::{parse_module, ModuleItem};
-use regex::Regex;
-use std::time::Instant;
-
-use std::io::Read;
-
-pub fn get_connect_module(name: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let mut res = reqwest::blocking::get(format!(
-        "https://raw.githubusercontent.com/episphere/questionnaire/refs/heads/main/prod/{}",
-        name
-    ))?;
-    let mut body = String::new();
-    res.read_to_string(&mut body)?;
-
-    Ok(body)
-}
-
-fn main() {
-    let markdown = get_connect_module("module1.txt").expect("Could not load the file");
-    // strip out the comments...
-    let re = Regex::new(r"//.*").unwrap();
-    let markdown = re.replace_all(&markdown, "");
-
-    // min,mean,max
-    let mut timings: (u128, f64, u128) = (0, 0., 0);
-    let mut sum: u128 = 0;
-
-    if false {
-        for i in 0..1000 {
-            let now = Instant::now();
-            let _ = parse_module(&markdown).unwrap();
-            let elapsed_time = now.elapsed().as_millis();
-            sum = elapsed_time + sum;
-            if i == 0 {
-                timings = (elapsed_time, sum as f64, elapsed_time);
-            } else {
-                timings = (
-                    elapsed_time.min(timings.0),
-                    (sum as f64) / (i as f64),
-                    elapsed_time.max(timings.2),
-                );
+use clap::{Parser, Subcommand, ValueEnum};
+use nom1::{
+    format_module, lint, render_html, Diagnostic, EdgeKind, LintConfig, LoadedModule, Module,
+    ModuleItem, ModuleLoader, ModuleSource, ParseError, Question, Severity,
+};
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+// Exit codes: 0 when everything is fine, 1 when a module has errors (or is
+// not formatted for fmt --check), 2 when the command itself failed, e.g. a
+// file could not be read or a url could not be fetched.
+#[derive(Parser)]
+#[command(
+    name = "nom1",
+    version,
+    about = "Tools for Quest questionnaire modules"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Fetch module names that are not local files from this url, e.g.
+    /// https://raw.githubusercontent.com/episphere/questionnaire/refs/heads/main/prod/
+    #[arg(long, global = true)]
+    base_url: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Parse modules and report syntax errors
+    Parse {
+        /// Module files, directories or urls, stdin when there are none or for -
+        files: Vec<String>,
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+    /// Check modules for duplicate ids, broken skips and other problems
+    Lint {
+        files: Vec<String>,
+        /// Rule settings, one "rule-id = off|info|warning|error" per line
+        #[arg(long)]
+        config: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+    /// Print modules in the canonical layout
+    Fmt {
+        files: Vec<String>,
+        /// Only report files that are not formatted
+        #[arg(long)]
+        check: bool,
+        /// Rewrite the files in place
+        #[arg(long, conflicts_with = "check")]
+        write: bool,
+    },
+    /// Render a module as Quest form markup
+    Render { file: Option<String> },
+    /// Export modules as JSON (needs the serde feature, on by default)
+    Export { files: Vec<String> },
+    /// Count the questions, loops, grids, responses and inputs
+    Stats {
+        files: Vec<String>,
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+    /// Print the navigation graph
+    Graph {
+        file: Option<String>,
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+    Debug,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum GraphFormat {
+    Dot,
+    Text,
+}
+
+// Every module the arguments name, stdin when there are none.  One loader
+// reads them all so a file or url named twice is only read once.
+fn read_sources(
+    files: &[String],
+    base_url: Option<&str>,
+) -> Result<Vec<LoadedModule>, Box<dyn Error>> {
+    let mut loader = ModuleLoader::new();
+    if files.is_empty() {
+        return Ok(loader.load(&ModuleSource::Stdin)?);
+    }
+    let mut sources = Vec::new();
+    for file in files {
+        sources.extend(loader.load(&ModuleSource::from_arg(file, base_url))?);
+    }
+    Ok(sources)
+}
+
+// for the commands that work on a single module
+fn read_source(
+    file: Option<String>,
+    base_url: Option<&str>,
+) -> Result<LoadedModule, Box<dyn Error>> {
+    let files: Vec<String> = file.into_iter().collect();
+    let mut sources = read_sources(&files, base_url)?;
+    if sources.len() > 1 {
+        return Err(format!(
+            "{} contains {} modules, expected one",
+            files[0],
+            sources.len()
+        )
+        .into());
+    }
+    Ok(sources.remove(0))
+}
+
+fn report_parse_error(source: &LoadedModule, e: &ParseError) {
+    eprintln!("{}:{}:{}: {}", source.name, e.line, e.column, e.message);
+    eprintln!("{}", e.snippet);
+}
+
+// the module, or None after reporting why it did not parse
+fn parse(source: &LoadedModule) -> Option<Module> {
+    match Module::parse(&source.text) {
+        Ok(module) => Some(module),
+        Err(e) => {
+            report_parse_error(source, &e);
+            None
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+fn print_json(value: &impl serde::Serialize) -> Result<(), Box<dyn Error>> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+#[cfg(not(feature = "serde"))]
+fn print_json<T>(_: &T) -> Result<(), Box<dyn Error>> {
+    Err("json output needs nom1 built with the serde feature".into())
+}
+
+// only read by print_json
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(not(feature = "serde"), allow(dead_code))]
+struct ParseReport {
+    file: String,
+    errors: Vec<ParseError>,
+}
+
+// only read by print_json
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(not(feature = "serde"), allow(dead_code))]
+struct LintReport {
+    file: String,
+    errors: Vec<ParseError>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+fn run_parse(sources: Vec<LoadedModule>, format: Format) -> Result<bool, Box<dyn Error>> {
+    let mut clean = true;
+    let mut reports = Vec::new();
+    for source in sources {
+        let (module, errors) = Module::parse_recovering(&source.text);
+        clean &= errors.is_empty();
+        match format {
+            Format::Text => {
+                for e in &errors {
+                    report_parse_error(&source, e);
+                }
+                if errors.is_empty() {
+                    println!("{}: {} items", source.name, module.items.len());
+                }
+            }
+            Format::Debug => println!("{:#?}", module),
+            Format::Json => reports.push(ParseReport {
+                file: source.name,
+                errors,
+            }),
+        }
+    }
+    if format == Format::Json {
+        print_json(&reports)?;
+    }
+    Ok(clean)
+}
+
+fn run_lint(
+    sources: Vec<LoadedModule>,
+    config: Option<PathBuf>,
+    format: Format,
+) -> Result<bool, Box<dyn Error>> {
+    let config = match config {
+        Some(path) => {
+            let text =
+                std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            LintConfig::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?
+        }
+        None => LintConfig::new(),
+    };
+    let mut clean = true;
+    let mut reports = Vec::new();
+    for source in sources {
+        let (module, errors) = Module::parse_recovering(&source.text);
+        let diagnostics = lint(&module, &config);
+        clean &= errors.is_empty();
+        clean &= diagnostics.iter().all(|d| d.severity != Severity::Error);
+        match format {
+            Format::Json => reports.push(LintReport {
+                file: source.name,
+                errors,
+                diagnostics,
+            }),
+            Format::Text | Format::Debug => {
+                for e in &errors {
+                    report_parse_error(&source, e);
+                }
+                for d in &diagnostics {
+                    println!(
+                        "{}:{}:{}: {}[{}]: {}",
+                        source.name,
+                        d.span.line,
+                        d.span.column,
+                        d.severity,
+                        d.rule.id(),
+                        d.message
+                    );
+                }
             }
         }
     }
+    if format == Format::Json {
+        print_json(&reports)?;
+    }
+    Ok(clean)
+}
 
-    if true {
-        let (remaining_text, module) = parse_module(&markdown).unwrap();
-        println!("Preamble:\n{:?}", module.preamble.trim());
-        for (indx, mi) in module.items.iter().enumerate() {
-            if indx < 1000 {
-                match mi {
-                    ModuleItem::Question(_q) => {
-                        //println!("{:?}", q.header)
+fn run_fmt(sources: Vec<LoadedModule>, check: bool, write: bool) -> Result<bool, Box<dyn Error>> {
+    let mut clean = true;
+    for source in sources {
+        let formatted = match format_module(&source.text) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                report_parse_error(&source, &e);
+                clean = false;
+                continue;
+            }
+        };
+        match (&source.path, check, write) {
+            (_, true, _) => {
+                if formatted != source.text {
+                    println!("{} is not formatted", source.name);
+                    clean = false;
+                }
+            }
+            (Some(path), _, true) => {
+                if formatted != source.text {
+                    std::fs::write(path, formatted)
+                        .map_err(|e| format!("{}: {}", source.name, e))?;
+                }
+            }
+            _ => print!("{}", formatted),
+        }
+    }
+    Ok(clean)
+}
+
+fn run_render(source: LoadedModule) -> Result<bool, Box<dyn Error>> {
+    let Some(module) = parse(&source) else {
+        return Ok(false);
+    };
+    print!("{}", render_html(&module));
+    Ok(true)
+}
+
+#[cfg(feature = "serde")]
+fn run_export(sources: Vec<LoadedModule>) -> Result<bool, Box<dyn Error>> {
+    let mut clean = true;
+    for source in sources {
+        match parse(&source) {
+            Some(module) => println!("{}", module.to_json()),
+            None => clean = false,
+        }
+    }
+    Ok(clean)
+}
+
+#[cfg(not(feature = "serde"))]
+fn run_export(_: Vec<LoadedModule>) -> Result<bool, Box<dyn Error>> {
+    Err("export needs nom1 built with the serde feature".into())
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Default)]
+struct Stats {
+    file: String,
+    questions: usize,
+    loops: usize,
+    grids: usize,
+    responses: usize,
+    inputs: usize,
+    // every id once loops are expanded
+    ids: usize,
+}
+
+impl Stats {
+    fn count(&mut self, items: &[ModuleItem]) {
+        for item in items {
+            match item {
+                ModuleItem::Question(q) => self.count_question(q),
+                ModuleItem::Loop(l) => {
+                    self.loops += 1;
+                    self.count(l.questions());
+                }
+                ModuleItem::Grid(g) => {
+                    self.grids += 1;
+                    self.responses += g.columns.len();
+                    for row in &g.rows {
+                        self.count_question(row);
                     }
-                    ModuleItem::Loop(l) => println!("====> LOOP:\n{:?}", l),
-                    ModuleItem::Grid(g) => println!("{:?}", g),
                 }
-                //println!("{}: {:?}", indx + 1, mi)
             }
         }
-        println!("anything additional???:\n{}", remaining_text);
     }
 
-    println!("Time to parse: {:?}", timings);
+    fn count_question(&mut self, q: &Question) {
+        self.questions += 1;
+        self.responses += q.responses.len();
+        self.inputs += q.inputs.len();
+        self.inputs += q.responses.iter().map(|r| r.inputs.len()).sum::<usize>();
+    }
+}
+
+fn run_stats(sources: Vec<LoadedModule>, format: Format) -> Result<bool, Box<dyn Error>> {
+    let mut clean = true;
+    let mut all = Vec::new();
+    for source in sources {
+        let Some(module) = parse(&source) else {
+            clean = false;
+            continue;
+        };
+        let mut stats = Stats {
+            file: source.name,
+            ids: module.symbols().symbols().len(),
+            ..Default::default()
+        };
+        stats.count(&module.items);
+        match format {
+            Format::Text => {
+                println!("{}", stats.file);
+                println!("  questions  {}", stats.questions);
+                println!("  loops      {}", stats.loops);
+                println!("  grids      {}", stats.grids);
+                println!("  responses  {}", stats.responses);
+                println!("  inputs     {}", stats.inputs);
+                println!("  ids        {}", stats.ids);
+            }
+            Format::Debug => println!("{:#?}", stats),
+            Format::Json => all.push(stats),
+        }
+    }
+    if format == Format::Json {
+        print_json(&all)?;
+    }
+    Ok(clean)
+}
+
+fn run_graph(source: LoadedModule, format: GraphFormat) -> Result<bool, Box<dyn Error>> {
+    let Some(module) = parse(&source) else {
+        return Ok(false);
+    };
+    let graph = module.navigation_graph();
+    if format == GraphFormat::Dot {
+        println!("digraph {:?} {{", source.name);
+        for node in &graph.nodes {
+            println!("  {:?};", node);
+        }
+    }
+    for edge in &graph.edges {
+        let label = match &edge.kind {
+            EdgeKind::Next => String::from("next"),
+            EdgeKind::Response(value) => format!("({})", value),
+            EdgeKind::NoResponse => String::from("#NR"),
+            EdgeKind::Goto => String::from("goto"),
+            EdgeKind::Repeat => String::from("repeat"),
+        };
+        match format {
+            GraphFormat::Dot if edge.kind == EdgeKind::Next => {
+                println!("  {:?} -> {:?};", edge.from, edge.to)
+            }
+            GraphFormat::Dot => println!("  {:?} -> {:?} [label={:?}];", edge.from, edge.to, label),
+            GraphFormat::Text => println!("{} -> {} {}", edge.from, edge.to, label),
+        }
+    }
+    if format == GraphFormat::Dot {
+        println!("}}");
+    }
+    Ok(true)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let base_url = cli.base_url.as_deref();
+    let result = match cli.command {
+        Command::Parse { files, format } => {
+            read_sources(&files, base_url).and_then(|sources| run_parse(sources, format))
+        }
+        Command::Lint {
+            files,
+            config,
+            format,
+        } => read_sources(&files, base_url).and_then(|sources| run_lint(sources, config, format)),
+        Command::Fmt {
+            files,
+            check,
+            write,
+        } => read_sources(&files, base_url).and_then(|sources| run_fmt(sources, check, write)),
+        Command::Render { file } => read_source(file, base_url).and_then(run_render),
+        Command::Export { files } => read_sources(&files, base_url).and_then(run_export),
+        Command::Stats { files, format } => {
+            read_sources(&files, base_url).and_then(|sources| run_stats(sources, format))
+        }
+        Command::Graph { file, format } => {
+            read_source(file, base_url).and_then(|source| run_graph(source, format))
+        }
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("nom1: {}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_count() {
+        let module = Module::parse(
+            "[Q1] name? |__|id=Q1_NAME|\n(1) other |__|id=Q1_OTH|\n\
+             <loop max=2>\n[L1] a\n</loop>\n\
+             <grid id=G>\nHow often?\n(1) never\n(2) always\n\
+             [G_1] first\n[G_2] other |__|id=G_2_OTH|\n</grid>",
+        )
+        .unwrap();
+        let mut stats = Stats::default();
+        stats.count(&module.items);
+        assert_eq!((stats.questions, stats.loops, stats.grids), (4, 1, 1));
+        assert_eq!((stats.responses, stats.inputs), (3, 3));
+    }
 }
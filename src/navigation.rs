@@ -0,0 +1,180 @@
+use crate::{Module, ModuleItem, Question};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum EdgeKind {
+    // fall through to the following question
+    Next,
+    // a "-> ID" on the response with this value
+    Response(String),
+    // a "#NR -> ID" line
+    NoResponse,
+    // a "-> ID" at the end of the question text
+    Goto,
+    // from the last question in a loop back to the first
+    Repeat,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub kind: EdgeKind,
+}
+
+// The question ids of a module in document order, and every way to get
+// from one question to another.  Edges may point at ids that are not in
+// the module (e.g. END or a typo), nodes only contains the parsed questions.
+#[derive(Debug, PartialEq, Default)]
+pub struct NavigationGraph {
+    pub nodes: Vec<String>,
+    pub edges: Vec<Edge>,
+}
+
+impl NavigationGraph {
+    pub fn successors<'a>(&'a self, id: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.edges
+            .iter()
+            .filter(move |edge| edge.from == id)
+            .map(|edge| edge.to.as_str())
+    }
+
+    pub fn predecessors<'a>(&'a self, id: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.edges
+            .iter()
+            .filter(move |edge| edge.to == id)
+            .map(|edge| edge.from.as_str())
+    }
+
+    fn add_edge(&mut self, from: &str, to: &str, kind: EdgeKind) {
+        self.edges.push(Edge {
+            from: String::from(from),
+            to: String::from(to),
+            kind,
+        });
+    }
+
+    fn add_question(&mut self, question: &Question) {
+        let id = question.id();
+        self.nodes.push(String::from(id));
+        for response in &question.responses {
+            if let Some(target) = &response.goto {
+                self.add_edge(id, target, EdgeKind::Response(response.value.clone()));
+            }
+        }
+        if let Some(target) = &question.no_response_goto {
+            self.add_edge(id, target, EdgeKind::NoResponse);
+        }
+        if let Some(target) = &question.goto {
+            self.add_edge(id, target, EdgeKind::Goto);
+        }
+    }
+
+    // adds the questions in items, returns the (first, last) question ids so
+    // the caller can link the block to its neighbours.
+    fn add_items(&mut self, items: &[ModuleItem]) -> Option<(String, String)> {
+        let mut first: Option<String> = None;
+        // the previous question, if it falls through to the next one
+        let mut previous: Option<String> = None;
+        let mut last: Option<String> = None;
+
+        for item in items {
+            let (start, end, falls_through) = match item {
+                ModuleItem::Question(q) => {
+                    self.add_question(q);
+                    (String::from(q.id()), String::from(q.id()), q.goto.is_none())
+                }
+                ModuleItem::Loop(l) => match self.add_items(&l.questions) {
+                    Some((start, end)) => {
+                        self.add_edge(&end, &start, EdgeKind::Repeat);
+                        (start, end, true)
+                    }
+                    None => continue,
+                },
+                ModuleItem::Grid(g) => {
+                    let rows: Vec<ModuleItem> = g
+                        .row_questions()
+                        .into_iter()
+                        .map(ModuleItem::Question)
+                        .collect();
+                    match self.add_items(&rows) {
+                        Some((start, end)) => (start, end, true),
+                        None => continue,
+                    }
+                }
+            };
+            if let Some(previous) = &previous {
+                self.add_edge(previous, &start, EdgeKind::Next);
+            }
+            if first.is_none() {
+                first = Some(start);
+            }
+            previous = if falls_through {
+                Some(end.clone())
+            } else {
+                None
+            };
+            last = Some(end);
+        }
+        first.zip(last)
+    }
+}
+
+impl Module {
+    pub fn navigation_graph(&self) -> NavigationGraph {
+        let mut graph = NavigationGraph::default();
+        graph.add_items(&self.items);
+        graph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_module;
+
+    #[test]
+    fn test_navigation_graph() {
+        let markdown = r#"
+[Q1] Do you smoke?
+(1) Yes
+(0) No -> Q3
+#NR -> Q4
+[Q2] How much? |__| -> Q4
+[Q3] Why not?
+<loop max=3>
+[L1] loop question 1
+[L2] loop question 2
+</loop>
+<grid id=G1>
+(1) yes
+(2) no -> Q4
+[G1_1] row 1
+[G1_2] row 2
+</grid>
+[Q4] The end
+"#;
+        let (_, module) = parse_module(markdown).unwrap();
+        let graph = module.navigation_graph();
+        assert_eq!(
+            graph.nodes,
+            vec!["Q1", "Q2", "Q3", "L1", "L2", "G1_1", "G1_2", "Q4"]
+        );
+
+        let q1: Vec<_> = graph.successors("Q1").collect();
+        assert_eq!(q1, vec!["Q3", "Q4", "Q2"]);
+        // Q2 always skips to Q4
+        let q2: Vec<_> = graph.successors("Q2").collect();
+        assert_eq!(q2, vec!["Q4"]);
+        let l2: Vec<_> = graph.successors("L2").collect();
+        assert_eq!(l2, vec!["L1", "G1_1"]);
+        let g1_1: Vec<_> = graph.successors("G1_1").collect();
+        assert_eq!(g1_1, vec!["Q4", "G1_2"]);
+        let q4: Vec<_> = graph.predecessors("Q4").collect();
+        assert_eq!(q4, vec!["Q1", "Q2", "G1_1", "G1_2", "G1_2"]);
+        assert!(graph.edges.contains(&Edge {
+            from: String::from("Q1"),
+            to: String::from("Q3"),
+            kind: EdgeKind::Response(String::from("0")),
+        }));
+    }
+}
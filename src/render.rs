@@ -0,0 +1,256 @@
+use crate::{
+    split_inputs, Grid, Input, InputField, Loop, Module, ModuleItem, Question, Response,
+    ResponseKind,
+};
+use std::collections::BTreeMap;
+
+// Renders a module the way the Quest renderer does in the browser, one
+// <form class="question"> per question:
+//
+// <form class="question" id="Q1" hardEdit="false" softEdit="false">
+// <div class="question-text">Do you smoke?</div>
+// <div class="response"><input type="radio" name="Q1" value="1" id="Q1_1" skipTo="Q5"><label for="Q1_1">Yes</label></div>
+// <div class="navigation">..BACK, NEXT and RESET ANSWER buttons..</div>
+// </form>
+//
+// Loops are unrolled up to their max inside a <div class="loop">, a grid is
+// one form with a table of rows against columns.
+pub fn render_html(module: &Module) -> String {
+    let mut html = String::new();
+    for item in &module.items {
+        render_item(item, &mut html);
+    }
+    html
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// ` key="value"` for each attribute, except those in skip
+fn render_attributes(attributes: &BTreeMap<String, String>, skip: &[&str], html: &mut String) {
+    for (key, value) in attributes {
+        if !skip.contains(&key.as_str()) {
+            html.push_str(&format!(" {}=\"{}\"", escape(key), escape(value)));
+        }
+    }
+}
+
+fn render_input(input: &Input, name: &str, html: &mut String) {
+    let kind = match &input.field {
+        InputField::TextBox => {
+            html.push_str(&format!("<textarea name=\"{}\"", escape(name)));
+            if let Some(id) = &input.id {
+                html.push_str(&format!(" id=\"{}\"", escape(id)));
+            }
+            render_attributes(&input.attributes, &["id"], html);
+            html.push_str("></textarea>");
+            return;
+        }
+        InputField::Text => "text",
+        InputField::Number { .. } => "number",
+        InputField::Date => "date",
+        InputField::Month => "month",
+        InputField::Email => "email",
+        InputField::Tel => "tel",
+    };
+    html.push_str(&format!(
+        "<input type=\"{}\" name=\"{}\"",
+        kind,
+        escape(name)
+    ));
+    if let Some(id) = &input.id {
+        html.push_str(&format!(" id=\"{}\"", escape(id)));
+    }
+    // min and max come along with the other attributes
+    render_attributes(&input.attributes, &["id"], html);
+    html.push('>');
+}
+
+// markdown with its input markers turned into inputs named after the question
+fn render_text(text: &str, name: &str, html: &mut String) {
+    for (piece, input) in split_inputs(text) {
+        html.push_str(&escape(piece).replace('\n', "<br>"));
+        if let Some(input) = input {
+            render_input(&input, name, html);
+        }
+    }
+}
+
+fn render_response(response: &Response, name: &str, html: &mut String) {
+    let id = format!("{}_{}", name, response.value);
+    html.push_str(&format!(
+        "<div class=\"response\"><input type=\"{}\" name=\"{}\" value=\"{}\" id=\"{}\"",
+        response_type(response),
+        escape(name),
+        escape(&response.value),
+        escape(&id)
+    ));
+    if let Some(goto) = &response.goto {
+        html.push_str(&format!(" skipTo=\"{}\"", escape(goto)));
+    }
+    render_attributes(&response.attributes, &[], html);
+    html.push_str(&format!("><label for=\"{}\">", escape(&id)));
+    render_text(&response.label, name, html);
+    html.push_str("</label></div>\n");
+}
+
+fn response_type(response: &Response) -> &'static str {
+    match response.kind {
+        ResponseKind::Radio => "radio",
+        ResponseKind::Checkbox => "checkbox",
+    }
+}
+
+fn render_buttons(html: &mut String) {
+    html.push_str(concat!(
+        "<div class=\"navigation\">",
+        "<input type=\"submit\" class=\"previous\" value=\"BACK\">",
+        "<input type=\"submit\" class=\"next\" value=\"NEXT\">",
+        "<input type=\"submit\" class=\"reset\" value=\"RESET ANSWER\">",
+        "</div>\n"
+    ));
+}
+
+fn render_question(question: &Question, html: &mut String) {
+    let header = &question.header;
+    html.push_str(&format!(
+        "<form class=\"question\" id=\"{}\" hardEdit=\"{}\" softEdit=\"{}\"",
+        escape(&header.id),
+        header.hard_required,
+        header.soft_required
+    ));
+    if let Some(goto) = &question.no_response_goto {
+        html.push_str(&format!(" noResponseSkipTo=\"{}\"", escape(goto)));
+    }
+    render_attributes(&header.attributes, &["id"], html);
+    html.push_str(">\n<div class=\"question-text\">");
+    render_text(&question.text, &header.id, html);
+    html.push_str("</div>\n");
+    for response in &question.responses {
+        render_response(response, &header.id, html);
+    }
+    render_buttons(html);
+    html.push_str("</form>\n");
+}
+
+fn render_loop(l: &Loop, html: &mut String) {
+    html.push_str("<div class=\"loop\"");
+    render_attributes(&l.tag().attributes(), &[], html);
+    html.push_str(">\n");
+    for item in l.expand(l.max.unwrap_or(1)) {
+        render_item(&item, html);
+    }
+    html.push_str("</div>\n");
+}
+
+fn render_grid(g: &Grid, html: &mut String) {
+    html.push_str("<form class=\"question grid\"");
+    render_attributes(&g.tag().attributes(), &[], html);
+    html.push_str(">\n<div class=\"question-text\">");
+    let name = g.id.as_deref().unwrap_or_default();
+    render_text(&g.prompt, name, html);
+    html.push_str("</div>\n<table class=\"quest-grid\">\n<tr><th></th>");
+    for column in &g.columns {
+        html.push_str("<th>");
+        render_text(&column.label, name, html);
+        html.push_str("</th>");
+    }
+    html.push_str("</tr>\n");
+    for row in &g.rows {
+        html.push_str(&format!("<tr id=\"{}\"><th>", escape(row.id())));
+        render_text(&row.text, row.id(), html);
+        html.push_str("</th>");
+        for column in &g.columns {
+            html.push_str(&format!(
+                "<td><input type=\"{}\" name=\"{}\" value=\"{}\" id=\"{}_{}\"",
+                response_type(column),
+                escape(row.id()),
+                escape(&column.value),
+                escape(row.id()),
+                escape(&column.value)
+            ));
+            if let Some(goto) = &column.goto {
+                html.push_str(&format!(" skipTo=\"{}\"", escape(goto)));
+            }
+            html.push_str("></td>");
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+    render_buttons(html);
+    html.push_str("</form>\n");
+}
+
+fn render_item(item: &ModuleItem, html: &mut String) {
+    match item {
+        ModuleItem::Question(q) => render_question(q, html),
+        ModuleItem::Loop(l) => render_loop(l, html),
+        ModuleItem::Grid(g) => render_grid(g, html),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_html() {
+        let markdown = r#"
+[Q1! displayif=equals(Q0,1)] Do you smoke <daily>?
+(1) Yes -> Q3
+(2) Other: |__|id=Q1_OTHER|
+#NR -> Q3
+[Q2] Age |__|min=0 max=120| and when? |date|
+<loop max=2>
+[L1] loop question
+[1] a
+[2] b
+</loop>
+<grid id=G1>
+How much?
+(1) little
+(2) a lot -> Q3
+[G1_1] row 1
+</grid>
+[Q3] The end
+"#;
+        let module = Module::parse(markdown).unwrap();
+        let html = render_html(&module);
+
+        assert!(html.starts_with(
+            "<form class=\"question\" id=\"Q1\" hardEdit=\"true\" softEdit=\"false\" \
+             noResponseSkipTo=\"Q3\" displayif=\"equals(Q0,1)\">\n\
+             <div class=\"question-text\">Do you smoke &lt;daily&gt;?</div>\n\
+             <div class=\"response\"><input type=\"radio\" name=\"Q1\" value=\"1\" id=\"Q1_1\" skipTo=\"Q3\">\
+             <label for=\"Q1_1\">Yes</label></div>\n"
+        ));
+        assert!(html.contains(
+            "<label for=\"Q1_2\">Other: <input type=\"text\" name=\"Q1\" id=\"Q1_OTHER\"></label>"
+        ));
+        assert!(html.contains(
+            "Age <input type=\"number\" name=\"Q2\" max=\"120\" min=\"0\"> and when? <input type=\"date\" name=\"Q2\">"
+        ));
+        assert!(
+            html.contains("<div class=\"loop\" max=\"2\">\n<form class=\"question\" id=\"L1_1\"")
+        );
+        assert!(html.contains("<input type=\"checkbox\" name=\"L1_2\" value=\"2\" id=\"L1_2_2\">"));
+        assert!(html.contains(
+            "<tr id=\"G1_1\"><th>row 1</th><td><input type=\"radio\" name=\"G1_1\" value=\"1\" id=\"G1_1_1\"></td>\
+             <td><input type=\"radio\" name=\"G1_1\" value=\"2\" id=\"G1_1_2\" skipTo=\"Q3\"></td></tr>"
+        ));
+        assert_eq!(html.matches("<form").count(), 6);
+        assert_eq!(html.matches("</form>").count(), 6);
+    }
+}
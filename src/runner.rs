@@ -0,0 +1,314 @@
+use crate::{evaluate, parse_expr, Expr, Loop, Module, ModuleItem, Question, ResponseStore, Value};
+
+// Walks a module the way a participant would, without a browser.  Loops are
+// unrolled up to their max (once without one), iterations past the answer to
+// the loop's counter question are skipped.  Each row of a grid is a step
+// whose responses are the grid's columns.
+#[derive(Debug)]
+pub struct SurveyRunner {
+    steps: Vec<Step>,
+    position: usize,
+    responses: ResponseStore,
+    visited: Vec<String>,
+    skipped: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Step {
+    question: Question,
+    // the displayif and counter of the loops around the question
+    conditions: Vec<Expr>,
+}
+
+fn loop_conditions(l: &Loop, iteration: usize, conditions: &[Expr]) -> Vec<Expr> {
+    let mut conditions = conditions.to_vec();
+    if let Some(Ok(expr)) = l.displayif.as_deref().map(parse_expr) {
+        conditions.push(expr);
+    }
+    if let Some(counter) = &l.counter {
+        conditions.push(Expr::call(
+            "greaterThanOrEqual",
+            vec![Expr::ident(counter), Expr::Number(iteration as f64)],
+        ));
+    }
+    conditions
+}
+
+fn flatten(items: &[ModuleItem], conditions: &[Expr], steps: &mut Vec<Step>) {
+    for item in items {
+        match item {
+            ModuleItem::Question(q) => steps.push(Step {
+                question: q.clone(),
+                conditions: conditions.to_vec(),
+            }),
+            ModuleItem::Loop(l) => {
+                let iterations = l.max.unwrap_or(1);
+                let body_length = l.questions().len();
+                let expanded = l.expand(iterations);
+                for (indx, body) in expanded.chunks(body_length.max(1)).enumerate() {
+                    flatten(body, &loop_conditions(l, indx + 1, conditions), steps);
+                }
+            }
+            ModuleItem::Grid(g) => {
+                let mut conditions = conditions.to_vec();
+                if let Some(Ok(expr)) = g.displayif.as_deref().map(parse_expr) {
+                    conditions.push(expr);
+                }
+                steps.extend(g.row_questions().into_iter().map(|question| Step {
+                    question,
+                    conditions: conditions.clone(),
+                }));
+            }
+        }
+    }
+}
+
+// id is target with one or more _n iteration suffixes, e.g. L1_1 or L1_2_1
+fn is_iteration_of(id: &str, target: &str) -> bool {
+    id.strip_prefix(target)
+        .and_then(|suffix| suffix.strip_prefix('_'))
+        .is_some_and(|suffix| {
+            suffix
+                .split('_')
+                .all(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        })
+}
+
+impl SurveyRunner {
+    pub fn new(module: &Module) -> Self {
+        let mut steps = Vec::new();
+        flatten(&module.items, &[], &mut steps);
+        let mut runner = SurveyRunner {
+            steps,
+            position: 0,
+            responses: ResponseStore::new(),
+            visited: Vec::new(),
+            skipped: Vec::new(),
+        };
+        runner.skip_hidden();
+        runner
+    }
+
+    pub fn current(&self) -> Option<&Question> {
+        self.steps.get(self.position).map(|step| &step.question)
+    }
+
+    pub fn current_id(&self) -> Option<&str> {
+        self.current().map(|q| q.id())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.current().is_none()
+    }
+
+    // the questions that were answered (or left blank), in order
+    pub fn visited(&self) -> &[String] {
+        &self.visited
+    }
+
+    // the questions passed over by a skip arrow or a false displayif
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    pub fn responses(&self) -> &ResponseStore {
+        &self.responses
+    }
+
+    fn is_displayed(&self, step: &Step) -> bool {
+        let displayif = match step.question.header.displayif() {
+            Some(Ok(expr)) => evaluate(&expr, &self.responses).is_truthy(),
+            // a displayif that does not parse is shown, as in the renderer.
+            Some(Err(_)) | None => true,
+        };
+        displayif
+            && step
+                .conditions
+                .iter()
+                .all(|expr| evaluate(expr, &self.responses).is_truthy())
+    }
+
+    fn skip_hidden(&mut self) {
+        while let Some(step) = self.steps.get(self.position) {
+            if self.is_displayed(step) {
+                break;
+            }
+            self.skipped.push(String::from(step.question.id()));
+            self.position += 1;
+        }
+    }
+
+    // Jumping forward skips everything in between, an unknown target
+    // (e.g. END) finishes the survey.  A question in a loop can be the
+    // target without its iteration suffix, -> L1 goes to L1_1.
+    fn goto(&mut self, target: &str) {
+        let indx = self
+            .steps
+            .iter()
+            .position(|s| s.question.id() == target)
+            .or_else(|| {
+                self.steps
+                    .iter()
+                    .position(|s| is_iteration_of(s.question.id(), target))
+            });
+        match indx {
+            Some(indx) => {
+                if indx > self.position {
+                    let passed = &self.steps[self.position + 1..indx];
+                    self.skipped
+                        .extend(passed.iter().map(|s| String::from(s.question.id())));
+                }
+                self.position = indx;
+            }
+            None => self.position = self.steps.len(),
+        }
+    }
+
+    // Records the answer to the current question and moves to the next
+    // question that should be displayed.  Value::Undefined means the
+    // participant did not respond.
+    pub fn answer(&mut self, value: impl Into<Value>) -> Option<&Question> {
+        let value = value.into();
+        let question = self.current()?;
+        let id = String::from(question.id());
+
+        let target = if value == Value::Undefined {
+            question.no_response_goto.clone()
+        } else {
+            question
+                .responses
+                .iter()
+                .find(|r| r.goto.is_some() && value.loosely_equals(&Value::from(&r.value[..])))
+                .and_then(|r| r.goto.clone())
+        }
+        .or_else(|| question.goto.clone());
+
+        if value == Value::Undefined {
+            self.responses.remove(&id);
+        } else {
+            self.responses.set(&id, value);
+        }
+        self.visited.push(id);
+
+        match target {
+            Some(target) => self.goto(&target),
+            None => self.position += 1,
+        }
+        self.skip_hidden();
+        self.current()
+    }
+
+    // answers questions until the answers run out or the survey ends,
+    // returning the question that would be asked next.
+    pub fn run<I, V>(&mut self, answers: I) -> Option<&Question>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        for value in answers {
+            if self.is_finished() {
+                break;
+            }
+            self.answer(value);
+        }
+        self.current()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_module;
+
+    const MODULE: &str = r#"
+[Q1] Do you smoke?
+(1) Yes
+(0) No -> Q4
+#NR -> Q5
+[Q2] How many per day? |__|
+[Q3 displayif=greaterThan(Q2,10)] Have you tried to quit?
+(1) Yes
+(0) No
+[Q3B] How many people live with you? |__|__|
+<loop max=3 counter=Q3B>
+[L1] loop question -> L2
+[L2] second loop question
+</loop>
+[Q4] Did you ever smoke?
+(1) Yes
+(0) No
+<grid id="G1" displayif=equals(Q4,1)>
+(1) A
+(2) B -> Q5
+[G1_1] row 1
+[G1_2] row 2
+</grid>
+[Q5] The end
+"#;
+
+    #[test]
+    fn test_runner_skips() {
+        let (_, module) = parse_module(MODULE).unwrap();
+        let mut runner = SurveyRunner::new(&module);
+        assert_eq!(runner.current_id(), Some("Q1"));
+
+        assert_eq!(runner.answer("0").map(|q| q.id()), Some("Q4"));
+        assert_eq!(
+            runner.skipped(),
+            &["Q2", "Q3", "Q3B", "L1_1", "L2_1", "L1_2", "L2_2", "L1_3", "L2_3"]
+        );
+
+        // the grid's displayif is false
+        runner.answer("0");
+        assert_eq!(runner.current_id(), Some("Q5"));
+        assert_eq!(runner.skipped()[9..], ["G1_1", "G1_2"]);
+        runner.answer("done");
+        assert!(runner.is_finished());
+        assert_eq!(runner.visited(), &["Q1", "Q4", "Q5"]);
+    }
+
+    #[test]
+    fn test_runner_displayif() {
+        let (_, module) = parse_module(MODULE).unwrap();
+        let mut runner = SurveyRunner::new(&module);
+        runner.run(["1", "5"]);
+        assert_eq!(runner.current_id(), Some("Q3B"));
+        assert_eq!(runner.skipped(), &["Q3"]);
+
+        let mut runner = SurveyRunner::new(&module);
+        runner.run(["1", "20", "0", "2", "a", "b", "c", "d", "1"]);
+        assert_eq!(runner.current_id(), Some("G1_1"));
+        // a column's skip arrow applies to every row
+        runner.answer("2");
+        assert_eq!(runner.current_id(), Some("Q5"));
+        // the counter question says two iterations
+        assert_eq!(runner.skipped(), &["L1_3", "L2_3", "G1_2"]);
+        assert_eq!(runner.responses().get("L2_2"), Some(&Value::from("d")));
+        assert_eq!(runner.responses().get("Q2"), Some(&Value::from("20")));
+    }
+
+    #[test]
+    fn test_runner_goto_into_loop() {
+        let markdown = "[Q1] Any children?\n(1) yes -> L1\n(0) no\n\
+                        [Q2] skipped\n\
+                        <loop max=2>\n[L1] name?\n</loop>\n[Q3] end";
+        let (_, module) = parse_module(markdown).unwrap();
+        let mut runner = SurveyRunner::new(&module);
+        assert_eq!(runner.answer("1").map(|q| q.id()), Some("L1_1"));
+        assert_eq!(runner.skipped(), &["Q2"]);
+        runner.run(["a", "b"]);
+        assert_eq!(runner.current_id(), Some("Q3"));
+        assert!(is_iteration_of("L1_2_1", "L1"));
+        assert!(!is_iteration_of("L1_OTH", "L1"));
+        assert!(!is_iteration_of("L12", "L1"));
+    }
+
+    #[test]
+    fn test_runner_no_response() {
+        let (_, module) = parse_module(MODULE).unwrap();
+        let mut runner = SurveyRunner::new(&module);
+        runner.answer(Value::Undefined);
+        assert_eq!(runner.current_id(), Some("Q5"));
+        assert!(!runner.responses().contains("Q1"));
+    }
+}
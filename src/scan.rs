@@ -0,0 +1,48 @@
+// Finding where the next question, loop or grid starts.  Everything that can
+// start one ([, < and the // of a comment that hides them) is ASCII, so the
+// scanner searches bytes with memchr and only looks at the characters around
+// a candidate.  An ASCII byte never occurs inside a multi byte UTF-8
+// character, so every candidate is a char boundary.
+use memchr::memchr3_iter;
+
+// the tags that end the text of a question.  A stray closing tag also ends
+// it so that it is reported instead of swallowed into the markdown.
+const TAGS: [&str; 4] = ["loop", "grid", "/loop", "/grid"];
+
+// The offset of the next [ID, <loop, <grid, </loop or </grid that is not in
+// a // comment, None when there is none.
+pub fn next_item(input: &str) -> Option<usize> {
+    let bytes = input.as_bytes();
+    // where the current comment ends, candidates before it are skipped
+    let mut comment_end = 0;
+    for indx in memchr3_iter(b'[', b'<', b'/', bytes) {
+        if indx < comment_end {
+            continue;
+        }
+        let rest = &input[indx + 1..];
+        match bytes[indx] {
+            b'/' if rest.starts_with('/') => match rest.find('\n') {
+                Some(end) => comment_end = indx + 1 + end,
+                None => return None,
+            },
+            b'[' if rest.chars().next().is_some_and(char::is_uppercase) => return Some(indx),
+            b'<' if TAGS.iter().any(|tag| rest.starts_with(tag)) => return Some(indx),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_item() {
+        let input = "¿Cuántas? // [Q1] in a comment\n(1) sí [a] <b> 1/2 [Ñ1] next";
+        assert_eq!(&input[next_item(input).unwrap()..], "[Ñ1] next");
+        assert_eq!(next_item("text </grid> more"), Some(5));
+        assert_eq!(next_item("only a comment // [Q1]"), None);
+        assert_eq!(next_item("[q1] lower case, <b>"), None);
+    }
+}
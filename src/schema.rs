@@ -0,0 +1,114 @@
+// The JSON form of a module, for tools that are not written in Rust.
+//
+// Version 1 of the schema:
+//
+// {
+//   "version": 1,
+//   "module": { "preamble": string, "items": [Item] }
+// }
+//
+// Item      one of
+//           { "type": "question", ...Question }
+//           { "type": "loop", "tag": Tag, "markdown": string,
+//             "questions": [Item], "id": string?, "max": number?,
+//             "first_question": string?, "displayif": string?,
+//             "counter": string?, "span": Span, "body_span": Span,
+//             "close_span": Span }
+//           { "type": "grid", "tag": Tag, "markdown": string, "id": string?,
+//             "displayif": string?, "prompt": string, "rows": [Question],
+//             "columns": [Response], "kind": "radio" | "checkbox",
+//             "span": Span, "body_span": Span, "close_span": Span }
+// Question  { "header": Header, "markdown": string, "text": string,
+//             "responses": [Response], "inputs": [Input], "goto": string?,
+//             "no_response_goto": string?, "span": Span,
+//             "header_span": Span, "markdown_span": Span }
+// Header    { "id": string, "soft_required": bool, "hard_required": bool,
+//             "attributes": { name: value } }
+// Response  { "kind": "radio" | "checkbox", "value": string, "label": string,
+//             "attributes": { name: value }, "inputs": [Input],
+//             "goto": string? }
+// Input     { "field": Field, "id": string?, "attributes": { name: value } }
+// Field     { "type": "text" | "date" | "month" | "email" | "tel" | "textbox" }
+//           or { "type": "number", "min": string?, "max": string? }
+// Tag       { "name": "loop" | "grid", "params": string, "span": Span,
+//             "params_span": Span }
+// Span      { "start": number, "end": number, "line": number,
+//             "column": number }
+//
+// string? is a string or null.  Span offsets are bytes into the module text,
+// end is exclusive, line and column are 1-based (0 for a node that was not
+// parsed from text).  Fields are only ever added within a version, anything
+// that renames or removes one bumps SCHEMA_VERSION.
+use crate::Module;
+use serde::de::Error;
+use serde::{Deserialize, Serialize};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Document<'a> {
+    version: u32,
+    module: &'a Module,
+}
+
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+impl Module {
+    pub fn to_json(&self) -> String {
+        let document = Document {
+            version: SCHEMA_VERSION,
+            module: self,
+        };
+        serde_json::to_string(&document).expect("a module is always valid json")
+    }
+
+    pub fn from_json(json: &str) -> Result<Module, serde_json::Error> {
+        let mut document: serde_json::Value = serde_json::from_str(json)?;
+        let Version { version } = Version::deserialize(&document)?;
+        if version != SCHEMA_VERSION {
+            return Err(serde_json::Error::custom(format!(
+                "unsupported schema version {}, expected {}",
+                version, SCHEMA_VERSION
+            )));
+        }
+        match document.get_mut("module") {
+            Some(module) => Module::deserialize(module.take()),
+            None => Err(serde_json::Error::missing_field("module")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ModuleItem;
+
+    #[test]
+    fn test_json_round_trip() {
+        let markdown = "intro\n[Q1! min=0] Age? |__|id=AGE|\n(1) Yes -> Q2\n<loop max=2>\n[L1] a\n</loop>\n<grid id=G1>\n[1] yes\n[G1_1] row\n</grid>\n[Q2] end";
+        let module = Module::parse(markdown).unwrap();
+        let json = module.to_json();
+        assert!(json.starts_with(r#"{"version":1,"module":{"preamble":"intro\n","items":[{"type":"question","header":{"id":"Q1""#));
+        assert!(json.contains(r#""field":{"type":"text"}"#));
+        assert!(json.contains(r#""kind":"checkbox""#));
+
+        let parsed = Module::from_json(&json).unwrap();
+        assert_eq!(parsed.preamble, module.preamble);
+        assert_eq!(parsed.items, module.items);
+        let ModuleItem::Loop(l) = &parsed.items[1] else {
+            panic!("expected a loop");
+        };
+        assert_eq!(l.tag().params, "max=2");
+        assert_eq!(
+            (l.span.start, l.close_span.line),
+            (module.items[1].span().start, 6)
+        );
+
+        let future = json.replacen(r#""version":1"#, r#""version":2"#, 1);
+        let e = Module::from_json(&future).unwrap_err();
+        assert_eq!(e.to_string(), "unsupported schema version 2, expected 1");
+    }
+}
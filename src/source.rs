@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// where the production Connect modules live, module names are appended
+pub const DEFAULT_BASE_URL: &str =
+    "https://raw.githubusercontent.com/episphere/questionnaire/refs/heads/main/prod/";
+
+// Somewhere to read module text from.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ModuleSource {
+    File(PathBuf),
+    // every .txt and .md file in the directory, in name order
+    Directory(PathBuf),
+    Stdin,
+    Url(String),
+}
+
+impl ModuleSource {
+    // base may or may not end in a /
+    pub fn url(base: &str, name: &str) -> Self {
+        ModuleSource::Url(format!("{}/{}", base.trim_end_matches('/'), name))
+    }
+
+    // a Connect module by name, e.g. module1.txt
+    pub fn connect(name: &str) -> Self {
+        ModuleSource::url(DEFAULT_BASE_URL, name)
+    }
+
+    // A command line argument: - is stdin, http(s):// is a url, anything
+    // else is a path.  With a base url a bare name that is not a local file
+    // is fetched from the base url instead.
+    pub fn from_arg(arg: &str, base_url: Option<&str>) -> Self {
+        let path = Path::new(arg);
+        if arg == "-" {
+            ModuleSource::Stdin
+        } else if arg.starts_with("http://") || arg.starts_with("https://") {
+            ModuleSource::Url(String::from(arg))
+        } else if path.is_dir() {
+            ModuleSource::Directory(PathBuf::from(arg))
+        } else if let (Some(base), false) = (base_url, path.exists()) {
+            ModuleSource::url(base, arg)
+        } else {
+            ModuleSource::File(PathBuf::from(arg))
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LoadedModule {
+    // the path or url it came from, <stdin> for stdin
+    pub name: String,
+    // set for modules read from a file, so they can be written back
+    pub path: Option<PathBuf>,
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io { name: String, error: std::io::Error },
+    // the server answered with something other than 200
+    Status { url: String, status: u16 },
+    Http { url: String, message: String },
+    // a directory without any module files
+    Empty(PathBuf),
+    // nom1 was built without the http feature
+    Unsupported(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io { name, error } => write!(f, "could not read {}: {}", name, error),
+            LoadError::Status { url, status } => {
+                write!(f, "could not fetch {}: the server answered {}", url, status)
+            }
+            LoadError::Http { url, message } => write!(f, "could not fetch {}: {}", url, message),
+            LoadError::Empty(path) => {
+                write!(f, "{} does not contain any modules", path.display())
+            }
+            LoadError::Unsupported(url) => write!(
+                f,
+                "could not fetch {}: nom1 was built without the http feature",
+                url
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+// Loads modules from any ModuleSource.  Everything it reads is cached, so a
+// module is only downloaded (and stdin only read) once per loader.
+#[derive(Debug, Default)]
+pub struct ModuleLoader {
+    cache: HashMap<String, String>,
+}
+
+impl ModuleLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // one module for a file, url or stdin, every module in a directory
+    pub fn load(&mut self, source: &ModuleSource) -> Result<Vec<LoadedModule>, LoadError> {
+        match source {
+            ModuleSource::File(path) => Ok(vec![self.load_file(path)?]),
+            ModuleSource::Directory(path) => self.load_directory(path),
+            ModuleSource::Stdin => {
+                let text = self.cached("<stdin>", || {
+                    let mut text = String::new();
+                    std::io::stdin()
+                        .read_to_string(&mut text)
+                        .map(|_| text)
+                        .map_err(|error| LoadError::Io {
+                            name: String::from("<stdin>"),
+                            error,
+                        })
+                })?;
+                Ok(vec![LoadedModule {
+                    name: String::from("<stdin>"),
+                    path: None,
+                    text,
+                }])
+            }
+            ModuleSource::Url(url) => {
+                let text = self.cached(url, || fetch(url))?;
+                Ok(vec![LoadedModule {
+                    name: url.clone(),
+                    path: None,
+                    text,
+                }])
+            }
+        }
+    }
+
+    fn cached(
+        &mut self,
+        key: &str,
+        read: impl FnOnce() -> Result<String, LoadError>,
+    ) -> Result<String, LoadError> {
+        if let Some(text) = self.cache.get(key) {
+            return Ok(text.clone());
+        }
+        let text = read()?;
+        self.cache.insert(String::from(key), text.clone());
+        Ok(text)
+    }
+
+    fn load_file(&mut self, path: &Path) -> Result<LoadedModule, LoadError> {
+        let name = path.display().to_string();
+        let text = self.cached(&name, || {
+            std::fs::read_to_string(path).map_err(|error| LoadError::Io {
+                name: name.clone(),
+                error,
+            })
+        })?;
+        Ok(LoadedModule {
+            name,
+            path: Some(path.to_path_buf()),
+            text,
+        })
+    }
+
+    fn load_directory(&mut self, path: &Path) -> Result<Vec<LoadedModule>, LoadError> {
+        let io_error = |error| LoadError::Io {
+            name: path.display().to_string(),
+            error,
+        };
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(path).map_err(io_error)? {
+            let file = entry.map_err(io_error)?.path();
+            let is_module = file
+                .extension()
+                .is_some_and(|extension| extension == "txt" || extension == "md");
+            if file.is_file() && is_module {
+                files.push(file);
+            }
+        }
+        if files.is_empty() {
+            return Err(LoadError::Empty(path.to_path_buf()));
+        }
+        files.sort();
+        files.iter().map(|file| self.load_file(file)).collect()
+    }
+}
+
+#[cfg(feature = "http")]
+fn fetch(url: &str) -> Result<String, LoadError> {
+    let http_error = |e: reqwest::Error| LoadError::Http {
+        url: String::from(url),
+        message: e.to_string(),
+    };
+    let response = reqwest::blocking::get(url).map_err(http_error)?;
+    if !response.status().is_success() {
+        return Err(LoadError::Status {
+            url: String::from(url),
+            status: response.status().as_u16(),
+        });
+    }
+    response.text().map_err(http_error)
+}
+
+#[cfg(not(feature = "http"))]
+fn fetch(url: &str) -> Result<String, LoadError> {
+    Err(LoadError::Unsupported(String::from(url)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nom1-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_load_files_and_directories() {
+        let dir = temp_dir("source");
+        std::fs::write(dir.join("b.txt"), "[B1] b").unwrap();
+        std::fs::write(dir.join("a.md"), "[A1] a").unwrap();
+        std::fs::write(dir.join("notes.json"), "{}").unwrap();
+
+        let mut loader = ModuleLoader::new();
+        let modules = loader.load(&ModuleSource::Directory(dir.clone())).unwrap();
+        let texts: Vec<_> = modules.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, vec!["[A1] a", "[B1] b"]);
+        assert_eq!(modules[0].path, Some(dir.join("a.md")));
+
+        // the second load comes from the cache
+        let file = dir.join("b.txt");
+        std::fs::write(&file, "[B1] changed").unwrap();
+        let modules = loader.load(&ModuleSource::File(file.clone())).unwrap();
+        assert_eq!(modules[0].text, "[B1] b");
+
+        let arg = file.to_str().unwrap();
+        assert_eq!(ModuleSource::from_arg(arg, None), ModuleSource::File(file));
+        assert_eq!(
+            ModuleSource::from_arg("module1.txt", Some("http://localhost:8000/prod/")),
+            ModuleSource::Url(String::from("http://localhost:8000/prod/module1.txt"))
+        );
+        assert_eq!(ModuleSource::from_arg("-", None), ModuleSource::Stdin);
+
+        let missing = dir.join("missing.txt");
+        let e = loader.load(&ModuleSource::File(missing)).unwrap_err();
+        assert!(e.to_string().starts_with("could not read "));
+        std::fs::remove_dir_all(&dir).unwrap();
+        let e = loader
+            .load(&ModuleSource::Directory(temp_dir("empty")))
+            .unwrap_err();
+        assert!(e.to_string().ends_with("does not contain any modules"));
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_load_url() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+
+        // a stand-in for the questionnaire repository that serves one module,
+        // answering 404 for anything else, and counts its requests.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}/prod", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = 0;
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let mut request_line = String::new();
+                BufReader::new(&stream)
+                    .read_line(&mut request_line)
+                    .unwrap();
+                requests += 1;
+                let (status, body) = if request_line.starts_with("GET /prod/module1.txt ") {
+                    ("200 OK", "[Q1] served")
+                } else {
+                    ("404 Not Found", "")
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+
+        let mut loader = ModuleLoader::new();
+        let source = ModuleSource::url(&base, "module1.txt");
+        assert_eq!(loader.load(&source).unwrap()[0].text, "[Q1] served");
+        assert_eq!(loader.load(&source).unwrap()[0].text, "[Q1] served");
+        let e = loader
+            .load(&ModuleSource::url(&base, "module9.txt"))
+            .unwrap_err();
+        assert!(matches!(e, LoadError::Status { status: 404, .. }));
+        assert_eq!(server.join().unwrap(), 2);
+    }
+}
@@ -0,0 +1,124 @@
+// Where a node came from in the module text.
+//
+// While parsing, start and end hold the addresses of the slice the node was
+// parsed from (see Span::locate), once the whole module is parsed they are
+// resolved into byte offsets and a line/column.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    // byte offsets, end is exclusive
+    pub start: usize,
+    pub end: usize,
+    // 1-based line and column (in characters) of start, 0 if the node did not
+    // come from the source (e.g. an expanded loop iteration with no original)
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub(crate) fn locate(slice: &str) -> Self {
+        let start = slice.as_ptr() as usize;
+        Span {
+            start,
+            end: start + slice.len(),
+            line: 0,
+            column: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start..self.end
+    }
+
+    // turns a located span into offsets into source.  Anything that was
+    // not parsed from source is reset to the default span.
+    pub(crate) fn resolve(&mut self, source: &str, lines: &LineIndex) {
+        let base = source.as_ptr() as usize;
+        if self.start < base || self.end > base + source.len() || self.end < self.start {
+            *self = Span::default();
+            return;
+        }
+        self.start -= base;
+        self.end -= base;
+        (self.line, self.column) = lines.line_column(source, self.start);
+    }
+
+    // Moves a resolved span by offset bytes and lines lines, e.g. from a
+    // slice of the source to the whole source or past an edit before it.
+    // Default spans stay as they are.
+    pub(crate) fn shift(&mut self, offset: isize, lines: isize) {
+        if self.line > 0 {
+            self.start = self.start.wrapping_add_signed(offset);
+            self.end = self.end.wrapping_add_signed(offset);
+            self.line = self.line.wrapping_add_signed(lines);
+        }
+    }
+}
+
+// the byte offset of slice, which must be a sub slice of source
+pub(crate) fn offset_of(source: &str, slice: &str) -> usize {
+    let offset = slice.as_ptr() as usize - source.as_ptr() as usize;
+    debug_assert!(offset + slice.len() <= source.len());
+    offset
+}
+
+// the 1-based line and column of a byte offset
+pub(crate) fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    LineIndex::new(source).line_column(source, offset)
+}
+
+// The start of every line, so a large module does not have to be rescanned
+// for each span.
+pub(crate) struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub(crate) fn new(source: &str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(memchr::memchr_iter(b'\n', source.as_bytes()).map(|indx| indx + 1));
+        LineIndex { starts }
+    }
+
+    pub(crate) fn line_column(&self, source: &str, offset: usize) -> (usize, usize) {
+        let line = match self.starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let column = source[self.starts[line]..offset].chars().count() + 1;
+        (line + 1, column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_span() {
+        let source = "[Q1] one\n¿[Q2] two";
+        let lines = LineIndex::new(source);
+        let mut span = Span::locate(&source[11..15]);
+        span.resolve(source, &lines);
+        assert_eq!(
+            (span.start, span.end, span.line, span.column),
+            (11, 15, 2, 2)
+        );
+
+        let elsewhere = String::from("[Q3]");
+        let mut span = Span::locate(&elsewhere);
+        span.resolve(source, &lines);
+        assert_eq!((span.start, span.end, span.line), (0, 0, 0));
+
+        assert_eq!(line_column(source, 0), (1, 1));
+        assert_eq!(line_column(source, 9), (2, 1));
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{Module, ModuleItem, Question, Span};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SymbolKind {
+    Question,
+    // a question in iteration n of a loop, e.g. L1_2
+    LoopQuestion { iteration: usize },
+    GridRow,
+    Grid,
+    Loop,
+    // an input with an id, e.g. |__|id=AGE|
+    Input,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Symbol {
+    pub id: String,
+    pub kind: SymbolKind,
+    // where the id is defined, an expanded loop question points at the
+    // question in the loop body
+    pub span: Span,
+}
+
+// Two definitions of the same id, first is the earlier one in the module.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Collision {
+    pub first: Symbol,
+    pub second: Symbol,
+}
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} is defined at line {}, column {} and again at line {}, column {}",
+            self.first.id,
+            self.first.span.line,
+            self.first.span.column,
+            self.second.span.line,
+            self.second.span.column
+        )
+    }
+}
+
+// Every id a module defines as the participant would see it: loop bodies are
+// expanded to their max (once without one) so L1 in a loop of 3 defines
+// L1_1, L1_2 and L1_3.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+    // the first definition of each id
+    index: HashMap<String, usize>,
+}
+
+impl SymbolTable {
+    pub fn new(module: &Module) -> Self {
+        let mut table = SymbolTable::default();
+        table.add_items(&module.items, None);
+        table
+    }
+
+    fn add(&mut self, id: &str, kind: SymbolKind, span: Span) {
+        self.index
+            .entry(String::from(id))
+            .or_insert(self.symbols.len());
+        self.symbols.push(Symbol {
+            id: String::from(id),
+            kind,
+            span,
+        });
+    }
+
+    fn add_question(&mut self, question: &Question, kind: SymbolKind) {
+        self.add(question.id(), kind, question.span);
+        let inputs = question
+            .inputs
+            .iter()
+            .chain(question.responses.iter().flat_map(|r| r.inputs.iter()));
+        for id in inputs.filter_map(|input| input.id.as_deref()) {
+            self.add(id, SymbolKind::Input, question.span);
+        }
+    }
+
+    fn add_items(&mut self, items: &[ModuleItem], iteration: Option<usize>) {
+        let question_kind = match iteration {
+            Some(iteration) => SymbolKind::LoopQuestion { iteration },
+            None => SymbolKind::Question,
+        };
+        for item in items {
+            match item {
+                ModuleItem::Question(q) => self.add_question(q, question_kind),
+                ModuleItem::Loop(l) => {
+                    if let Some(id) = &l.id {
+                        self.add(id, SymbolKind::Loop, l.span);
+                    }
+                    let body_length = l.questions().len().max(1);
+                    let expanded = l.expand(l.max.unwrap_or(1));
+                    for (indx, body) in expanded.chunks(body_length).enumerate() {
+                        self.add_items(body, Some(indx + 1));
+                    }
+                }
+                ModuleItem::Grid(g) => {
+                    if let Some(id) = &g.id {
+                        self.add(id, SymbolKind::Grid, g.span);
+                    }
+                    for row in &g.rows {
+                        self.add_question(row, SymbolKind::GridRow);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Symbol> {
+        self.index.get(id).map(|&indx| &self.symbols[indx])
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.index.contains_key(id)
+    }
+
+    // in document order, including every redefinition
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    // each redefinition of an id paired with its first definition
+    pub fn collisions(&self) -> Vec<Collision> {
+        self.symbols
+            .iter()
+            .enumerate()
+            .filter_map(|(indx, symbol)| {
+                let first = self.index[&symbol.id];
+                (first != indx).then(|| Collision {
+                    first: self.symbols[first].clone(),
+                    second: symbol.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl Module {
+    pub fn symbols(&self) -> SymbolTable {
+        SymbolTable::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol_collisions() {
+        let markdown = r#"[Q1] one |__|id=AGE|
+<loop id=LOOP max=2>
+[L1] loop question
+</loop>
+[L1_2] clashes with the second iteration
+<grid id=G1>
+(1) yes
+[G1_1] row
+[Q1] clashes with the first question
+</grid>
+[AGE] clashes with an input"#;
+        let module = Module::parse(markdown).unwrap();
+        let symbols = module.symbols();
+
+        let l1_2 = symbols.get("L1_2").unwrap();
+        assert_eq!(l1_2.kind, SymbolKind::LoopQuestion { iteration: 2 });
+        assert_eq!(l1_2.span.line, 3);
+        assert!(!symbols.contains("L1"));
+        assert_eq!(symbols.get("G1_1").unwrap().kind, SymbolKind::GridRow);
+        assert_eq!(symbols.get("LOOP").unwrap().kind, SymbolKind::Loop);
+
+        let collisions: Vec<_> = symbols
+            .collisions()
+            .iter()
+            .map(|c| (c.first.id.clone(), c.first.span.line, c.second.span.line))
+            .collect();
+        assert_eq!(
+            collisions,
+            vec![
+                (String::from("L1_2"), 3, 5),
+                (String::from("Q1"), 1, 9),
+                (String::from("AGE"), 1, 11),
+            ]
+        );
+        assert_eq!(
+            symbols.collisions()[1].to_string(),
+            "Q1 is defined at line 1, column 1 and again at line 9, column 1"
+        );
+    }
+}